@@ -0,0 +1,44 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+
+/// Runs `script` `RUNS` times and returns the fastest run and its output.
+fn best_of(script: &Path) -> (Duration, String) {
+    let mut best = Duration::MAX;
+    let mut stdout = String::new();
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_intrprtr-rs"))
+            .arg(script)
+            .output()
+            .expect("failed to run the interpreter");
+        let elapsed = start.elapsed();
+        stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(output.status.success(), "benchmark script failed: {stdout}");
+        best = best.min(elapsed);
+    }
+    (best, stdout)
+}
+
+fn main() {
+    let benches = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches");
+    let (best, _) = best_of(&benches.join("variables.lox"));
+    println!("variables.lox: best of {RUNS} runs {best:.2?}");
+
+    // Wrapping the script in a block turns each global into a local, so the
+    // two runs differ only in how variables are stored.
+    let source = fs::read_to_string(benches.join("lookups.lox")).expect("failed to read lookups.lox");
+    let wrapped = env::temp_dir().join(format!("intrprtr-rs-lookups-{}.lox", std::process::id()));
+    fs::write(&wrapped, "{\n".to_string() + &source + "}\n").expect("failed to write the wrapped script");
+    let (by_name, globals) = best_of(&benches.join("lookups.lox"));
+    let (by_slot, locals) = best_of(&wrapped);
+    fs::remove_file(&wrapped).ok();
+    assert_eq!(globals, locals, "both forms of lookups.lox must compute the same result");
+
+    println!("lookups.lox as globals (by name): best of {RUNS} runs {by_name:.2?}");
+    println!("lookups.lox as locals (by slot): best of {RUNS} runs {by_slot:.2?}, {:.2}x faster", by_name.as_secs_f64() / by_slot.as_secs_f64());
+}
@@ -1,204 +1,629 @@
-use std::io::{self, Write};
+use std::rc::Rc;
 
-use crate::{token::{TokenType::{*, self}, Token}, environment::*};
-use crate::error_handling;
+#[allow(clippy::enum_glob_use)]
+use crate::token::TokenType::{*, self};
+use crate::{token::{Literal, Span, Token}, environment::{Expression, FunctionDeclaration, Statement, State}};
 
+fn sexpr(colour: bool, code: &str, parts: &[String]) -> String {
+    let mut inner = String::new();
+    for part in parts {
+        if !inner.is_empty() && !part.starts_with('\n') {
+            inner.push(' ');
+        }
+        inner += part;
+    }
+
+    if colour {
+        format!("\x1b[{code}m(\x1b[0m{inner}\x1b[{code}m)\x1b[0m")
+    } else {
+        format!("({inner})")
+    }
+}
+
+fn literal(token: &Token) -> String {
+    match &token.literal {
+        Literal::String(text) => format!("{text:?}"),
+        _ => token.lexeme.clone(),
+    }
+}
+
+fn function_parts(declaration: &FunctionDeclaration, colour: bool, indent: usize) -> Vec<String> {
+    let params: Vec<String> = declaration.params.iter().map(|param| param.lexeme.clone()).collect();
+    let mut parts = vec![format!("({})", params.join(" "))];
+    parts.extend(body(&declaration.body, colour, indent + 1));
+    parts
+}
 
+fn body(statements: &[Statement], colour: bool, indent: usize) -> Vec<String> {
+    statements.iter().map(|statement| "\n".to_string() + &"  ".repeat(indent) + &print_statement(statement, colour, indent)).collect()
+}
 
-fn _print(expr: &Expression) {
+/// Renders an expression as an S-expression, optionally with ANSI colours.
+pub fn print_expression(expr: &Expression, colour: bool, indent: usize) -> String {
+    let mut print = |expr: &Expression| print_expression(expr, colour, indent);
     match expr {
-        Expression::Binary(left, op, right) => {
-            print!("\x1b[1;34m( \x1b[0m{} ", op.lexeme); io::stdout().flush().unwrap();
-            _print(&*left); io::stdout().flush().unwrap();
-            _print(&*right); io::stdout().flush().unwrap();
-            print!("\x1b[1;34m)\x1b[0m "); io::stdout().flush().unwrap();
-        },
-        Expression::Grouping(expr) => {
-            print!(" \x1b[1;35m( \x1b[0m"); io::stdout().flush().unwrap();
-            _print(&*expr); io::stdout().flush().unwrap();
-            print!("\x1b[1;35m)\x1b[0m "); io::stdout().flush().unwrap();
-        },
-        Expression::Literal(value) => {
-            print!("{} ", value.lexeme); io::stdout().flush().unwrap();
-        },
-        Expression::Unary(op, expr) => {
-            print!(" \x1b[1;33m( \x1b[0m{} ", op.lexeme); io::stdout().flush().unwrap();
-            _print(&*expr); io::stdout().flush().unwrap();
-            print!("\x1b[1;33m)\x1b[0m "); io::stdout().flush().unwrap();
-        },
-        Expression::Variable(value) => {
-            print!("{} ", value.lexeme); io::stdout().flush().unwrap();
-        }
+        Expression::Binary(left, op, right) | Expression::Logical(left, op, right) => {
+            sexpr(colour, "1;34", &[op.lexeme.clone(), print(left), print(right)])
+        },
+        Expression::Grouping(expr) => sexpr(colour, "1;35", &["group".to_string(), print(expr)]),
+        Expression::Literal(value) => literal(value),
+        Expression::Variable(name, _) | Expression::This(name, _) => name.lexeme.clone(),
+        Expression::Unary(op, expr) => sexpr(colour, "1;33", &[op.lexeme.clone(), print(expr)]),
+        Expression::Assign(name, value, _) => sexpr(colour, "1;34", &["=".to_string(), name.lexeme.clone(), print(value)]),
+        Expression::CompoundAssign(name, op, value, _) => {
+            sexpr(colour, "1;34", &[op.lexeme.clone(), name.lexeme.clone(), print(value)])
+        },
+        Expression::Update(name, op, prefix, _) => {
+            let text = if *prefix { op.lexeme.clone() + &name.lexeme } else { name.lexeme.clone() + &op.lexeme };
+            sexpr(colour, "1;33", &[text])
+        },
+        Expression::Lambda(declaration) => {
+            let mut parts = vec!["fn".to_string()];
+            parts.extend(function_parts(declaration, colour, indent));
+            sexpr(colour, "1;36", &parts)
+        },
+        Expression::Call(callee, _, arguments) => {
+            let mut parts = vec!["call".to_string(), print(callee)];
+            parts.extend(arguments.iter().map(&mut print));
+            sexpr(colour, "1;36", &parts)
+        },
+        Expression::Get(object, name) => sexpr(colour, "1;36", &[".".to_string(), print(object), name.lexeme.clone()]),
+        Expression::Index(object, _, index) => sexpr(colour, "1;36", &["[]".to_string(), print(object), print(index)]),
+        Expression::Set(object, name, value) => {
+            sexpr(colour, "1;34", &["=".to_string(), sexpr(colour, "1;36", &[".".to_string(), print(object), name.lexeme.clone()]), print(value)])
+        },
+        Expression::Super(_, method, _) => sexpr(colour, "1;36", &[".".to_string(), "super".to_string(), method.lexeme.clone()]),
+        Expression::Interpolation(parts) => {
+            let mut items = vec!["interpolate".to_string()];
+            items.extend(parts.iter().map(&mut print));
+            sexpr(colour, "1;32", &items)
+        },
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Vec<Statement> {
+/// Renders a statement as an S-expression; nested bodies go on their own indented lines.
+pub fn print_statement(statement: &Statement, colour: bool, indent: usize) -> String {
+    let expression = |expr: &Expression| print_expression(expr, colour, indent);
+    match statement {
+        Statement::Expression(expr) => expression(expr),
+        Statement::Print(expr) => sexpr(colour, "1;37", &["print".to_string(), expression(expr)]),
+        Statement::Var(name, initializer) => sexpr(colour, "1;37", &["let".to_string(), name.lexeme.clone(), expression(initializer)]),
+        Statement::Block(statements) => {
+            let mut parts = vec!["block".to_string()];
+            parts.extend(body(statements, colour, indent + 1));
+            sexpr(colour, "1;37", &parts)
+        },
+        Statement::If(condition, then_branch, else_branch) => {
+            let mut parts = vec!["if".to_string(), expression(condition)];
+            parts.extend(body(std::slice::from_ref(then_branch), colour, indent + 1));
+            if let Some(else_branch) = else_branch {
+                parts.extend(body(std::slice::from_ref(else_branch), colour, indent + 1));
+            }
+            sexpr(colour, "1;37", &parts)
+        },
+        Statement::While(condition, statement) => {
+            let mut parts = vec!["while".to_string(), expression(condition)];
+            parts.extend(body(std::slice::from_ref(statement), colour, indent + 1));
+            sexpr(colour, "1;37", &parts)
+        },
+        Statement::Function(declaration) => {
+            let mut parts = vec!["fn".to_string(), declaration.name.lexeme.clone()];
+            parts.extend(function_parts(declaration, colour, indent));
+            sexpr(colour, "1;36", &parts)
+        },
+        Statement::Return(_, value) => {
+            let mut parts = vec!["return".to_string()];
+            parts.extend(value.iter().map(expression));
+            sexpr(colour, "1;37", &parts)
+        },
+        Statement::Class(name, superclass, methods) => {
+            let mut parts = vec!["class".to_string(), name.lexeme.clone()];
+            if let Some(superclass) = superclass {
+                parts.push("<".to_string());
+                parts.push(expression(superclass));
+            }
+            for method in methods {
+                let mut method_parts = vec!["fn".to_string(), method.name.lexeme.clone()];
+                method_parts.extend(function_parts(method, colour, indent + 1));
+                parts.push("\n".to_string() + &"  ".repeat(indent + 1) + &sexpr(colour, "1;36", &method_parts));
+            }
+            sexpr(colour, "1;37", &parts)
+        },
+    }
+}
+
+/// Renders a whole program, one top-level statement per line.
+pub fn print(statements: &[Statement], colour: bool) -> String {
+    statements.iter().map(|statement| print_statement(statement, colour, 0) + "\n").collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(span: Span, message: &str) -> ParseError {
+        ParseError { span, message: message.to_string() }
+    }
+}
+
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Statement>, Vec<ParseError>> {
     let mut state = State {
         tokens,
         current: 0,
+        errors: Vec::new(),
     };
 
     let mut statements: Vec<Statement> = Vec::new();
 
     while !is_at_end(&mut state) {
-        statements.push(declaration(&mut state));
+        if match_types(&mut state, vec![SEMICOLON]) {
+            continue;
+        }
+        match declaration(&mut state) {
+            Ok(statement) => statements.push(statement),
+            Err(error) => {
+                state.errors.push(error);
+                synchronize(&mut state);
+                match_types(&mut state, vec![RIGHT_BRACE]);
+            },
+        }
+    }
+
+    if state.errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(state.errors)
     }
-    
-    statements
 }
 
-fn declaration(state: &mut State) -> Statement {
-    if _match(state, vec![VAR]) {
+fn declaration(state: &mut State) -> Result<Statement, ParseError> {
+    if match_types(state, vec![VAR]) {
         return var_declaration(state);
     }
+    if match_types(state, vec![CLASS]) {
+        return class_declaration(state);
+    }
+    if check(state, FUN) && state.tokens[state.current + 1].token_type == IDENTIFIER {
+        advance(state);
+        return Ok(Statement::Function(Rc::new(function(state)?)));
+    }
 
     statement(state)
 }
 
-fn var_declaration(state: &mut State) -> Statement {
-    let name = consume(state, IDENTIFIER, "variable");
-    
-    let initializer;
-    if _match(state, vec![EQUAL]) {
-        initializer = expression(state);
+fn class_declaration(state: &mut State) -> Result<Statement, ParseError> {
+    let name = consume(state, IDENTIFIER, "class name")?;
+
+    let superclass = if match_types(state, vec![LESS]) {
+        Some(Expression::Variable(consume(state, IDENTIFIER, "superclass name")?, None))
     } else {
-        initializer = Expression::Literal(Token::new(NIL, "null".to_string(), vec!['n', 'i', 'l'], 0, 1));
+        None
+    };
+
+    skip_newlines(state);
+    consume(state, LEFT_BRACE, "{")?;
+    let mut methods = Vec::new();
+    while !check(state, RIGHT_BRACE) && !is_at_end(state) {
+        if match_types(state, vec![SEMICOLON]) {
+            continue;
+        }
+        match_types(state, vec![FUN]);
+        methods.push(Rc::new(function(state)?));
+    }
+    consume(state, RIGHT_BRACE, "}")?;
+
+    Ok(Statement::Class(name, superclass, methods))
+}
+
+fn function(state: &mut State) -> Result<FunctionDeclaration, ParseError> {
+    let name = consume(state, IDENTIFIER, "function name")?;
+    let params = parameters(state)?;
+    skip_newlines(state);
+    consume(state, LEFT_BRACE, "{")?;
+    let body = block(state)?;
+    Ok(FunctionDeclaration { name, params, body })
+}
+
+fn lambda(state: &mut State) -> Result<Expression, ParseError> {
+    let name = previous(state);
+    let params = parameters(state)?;
+
+    if match_types(state, vec![ARROW]) {
+        let body = expression(state)?;
+        let body = vec![Statement::Return(name.clone(), Some(body))];
+        return Ok(Expression::Lambda(Rc::new(FunctionDeclaration { name, params, body })));
     }
-    consume(state, SEMICOLON, ";");
-    Statement::Var(name, initializer)
+
+    skip_newlines(state);
+    consume(state, LEFT_BRACE, "{")?;
+    let body = block(state)?;
+    Ok(Expression::Lambda(Rc::new(FunctionDeclaration { name, params, body })))
 }
 
-fn statement(state: &mut State) -> Statement {
-    if _match(state, vec![PRINT]) {
+fn parameters(state: &mut State) -> Result<Vec<Token>, ParseError> {
+    consume(state, LEFT_PAREN, "(")?;
+    let mut params = Vec::new();
+    if !check(state, RIGHT_PAREN) {
+        loop {
+            params.push(consume(state, IDENTIFIER, "parameter name")?);
+            if !match_types(state, vec![COMMA]) {
+                break;
+            }
+        }
+    }
+    consume(state, RIGHT_PAREN, ")")?;
+    Ok(params)
+}
+
+fn var_declaration(state: &mut State) -> Result<Statement, ParseError> {
+    let name = consume(state, IDENTIFIER, "variable")?;
+    
+    let initializer = if match_types(state, vec![EQUAL]) {
+        expression(state)?
+    } else {
+        Expression::Literal(Token::new(NIL, "null".to_string(), Literal::None, name.span))
+    };
+    terminator(state)?;
+    Ok(Statement::Var(name, initializer))
+}
+
+fn statement(state: &mut State) -> Result<Statement, ParseError> {
+    if match_types(state, vec![PRINT]) {
         return print_stmt(state);
     }
+    if match_types(state, vec![LEFT_BRACE]) {
+        return Ok(Statement::Block(block(state)?));
+    }
+    if match_types(state, vec![IF]) {
+        return if_stmt(state);
+    }
+    if match_types(state, vec![WHILE]) {
+        return while_stmt(state);
+    }
+    if match_types(state, vec![FOR]) {
+        return for_stmt(state);
+    }
+    if match_types(state, vec![RETURN]) {
+        return return_stmt(state);
+    }
     expr_stmt(state)
 }
 
-fn print_stmt(state: &mut State) -> Statement {
-    let value = expression(state);
-    consume(state, SEMICOLON, "a semicolon or a line break");
-    Statement::Print(value)
+fn condition(state: &mut State) -> Result<Expression, ParseError> {
+    consume(state, LEFT_PAREN, "(")?;
+    let condition = expression(state)?;
+    consume(state, RIGHT_PAREN, ")")?;
+    skip_newlines(state);
+    Ok(condition)
 }
 
-fn expr_stmt(state: &mut State) -> Statement {
-    let expr = expression(state);
-    consume(state, SEMICOLON, "a semicolon or a line break");
-    Statement::Expression(expr)
+fn if_stmt(state: &mut State) -> Result<Statement, ParseError> {
+    let condition = condition(state)?;
+    let then_branch = statement(state)?;
+
+    let mut lookahead = state.current;
+    while is_newline(&state.tokens[lookahead]) {
+        lookahead += 1;
+    }
+    let else_branch = if state.tokens[lookahead].token_type == ELSE {
+        state.current = lookahead + 1;
+        skip_newlines(state);
+        Some(Box::new(statement(state)?))
+    } else {
+        None
+    };
+
+    Ok(Statement::If(condition, Box::new(then_branch), else_branch))
 }
 
-fn expression(state: &mut State) -> Expression {
-    equality(state)
+fn return_stmt(state: &mut State) -> Result<Statement, ParseError> {
+    let keyword = previous(state);
+    let value = if check(state, SEMICOLON) || check(state, RIGHT_BRACE) || is_at_end(state) {
+        None
+    } else {
+        Some(expression(state)?)
+    };
+    terminator(state)?;
+    Ok(Statement::Return(keyword, value))
 }
 
-fn equality(state: &mut State) -> Expression {
-    let mut expr = comparison(state);
+fn while_stmt(state: &mut State) -> Result<Statement, ParseError> {
+    let condition = condition(state)?;
+    let body = statement(state)?;
+    Ok(Statement::While(condition, Box::new(body)))
+}
 
-    while _match(state, vec![BANG_EQUAL, EQUAL_EQUAL]) {
-        let operator = previous(state);
-        let right = comparison(state);
-        expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
+fn for_stmt(state: &mut State) -> Result<Statement, ParseError> {
+    let keyword = previous(state);
+    consume(state, LEFT_PAREN, "(")?;
+
+    let initializer = if match_types(state, vec![SEMICOLON]) {
+        None
+    } else if match_types(state, vec![VAR]) {
+        Some(var_declaration(state)?)
+    } else {
+        Some(expr_stmt(state)?)
+    };
+
+    let condition = if check(state, SEMICOLON) {
+        Expression::Literal(Token::new(TRUE, "true".to_string(), Literal::None, keyword.span))
+    } else {
+        expression(state)?
+    };
+    consume(state, SEMICOLON, ";")?;
+
+    let increment = if check(state, RIGHT_PAREN) {
+        None
+    } else {
+        Some(expression(state)?)
+    };
+    consume(state, RIGHT_PAREN, ")")?;
+    skip_newlines(state);
+
+    let mut body = statement(state)?;
+    if let Some(increment) = increment {
+        body = Statement::Block(vec![body, Statement::Expression(increment)]);
+    }
+    body = Statement::While(condition, Box::new(body));
+    if let Some(initializer) = initializer {
+        body = Statement::Block(vec![initializer, body]);
     }
 
-    expr
+    Ok(body)
 }
 
-fn comparison(state: &mut State) -> Expression {
-    let mut expr = term(state);
+fn block(state: &mut State) -> Result<Vec<Statement>, ParseError> {
+    let mut statements = Vec::new();
 
-    while _match(state, vec![GREATER, GREATER_EQUAL, LESS, LESS_EQUAL]) {
-        let operator = previous(state);
-        let right = term(state);
-        expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
+    while !check(state, RIGHT_BRACE) && !is_at_end(state) {
+        if match_types(state, vec![SEMICOLON]) {
+            continue;
+        }
+        match declaration(state) {
+            Ok(statement) => statements.push(statement),
+            Err(error) => {
+                state.errors.push(error);
+                synchronize(state);
+            },
+        }
     }
 
-    expr
+    consume(state, RIGHT_BRACE, "}")?;
+    Ok(statements)
 }
 
-fn term(state: &mut State) -> Expression {
-    let mut expr = factor(state);
+fn print_stmt(state: &mut State) -> Result<Statement, ParseError> {
+    let value = expression(state)?;
+    terminator(state)?;
+    Ok(Statement::Print(value))
+}
 
-    while _match(state, vec![MINUS, PLUS]) {
-        let operator = previous(state);
-        let right = factor(state);
-        expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
+fn expr_stmt(state: &mut State) -> Result<Statement, ParseError> {
+    let expr = expression(state)?;
+    terminator(state)?;
+    Ok(Statement::Expression(expr))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+const PREFIX_POWER: u8 = 8;
+
+/// Binding power and associativity of every infix and postfix operator.
+fn infix_rule(token_type: TokenType) -> Option<(u8, Associativity)> {
+    match token_type {
+        EQUAL | PLUS_EQUAL | MINUS_EQUAL | STAR_EQUAL | SLASH_EQUAL | PERCENT_EQUAL => Some((1, Associativity::Right)),
+        OR => Some((2, Associativity::Left)),
+        AND => Some((3, Associativity::Left)),
+        BANG_EQUAL | EQUAL_EQUAL => Some((4, Associativity::Left)),
+        GREATER | GREATER_EQUAL | LESS | LESS_EQUAL => Some((5, Associativity::Left)),
+        MINUS | PLUS => Some((6, Associativity::Left)),
+        SLASH | STAR | PERCENT => Some((7, Associativity::Left)),
+        LEFT_PAREN | LEFT_BRACKET | DOT | PLUS_PLUS | MINUS_MINUS => Some((9, Associativity::Left)),
+        _ => None,
     }
+}
 
-    expr
+fn expression(state: &mut State) -> Result<Expression, ParseError> {
+    parse_precedence(state, 0)
 }
 
-fn factor(state: &mut State) -> Expression {
-    let mut expr = unary(state);
+fn parse_precedence(state: &mut State, min_power: u8) -> Result<Expression, ParseError> {
+    let mut expr = prefix(state)?;
 
-    if _match(state, vec![SLASH, STAR]) {
-        let operator = previous(state);
-        let right = unary(state);
-        expr = Expression::Binary(Box::new(expr), operator, Box::new(right));
+    while let Some((power, associativity)) = infix_rule(peek(state).token_type) {
+        if power < min_power {
+            break;
+        }
+        let operator = advance(state);
+        let right_power = match associativity {
+            Associativity::Left => power + 1,
+            Associativity::Right => power,
+        };
+        expr = infix(state, expr, operator, right_power)?;
     }
 
-    expr
+    Ok(expr)
 }
 
-fn unary(state: &mut State) -> Expression {
-    if _match(state, vec![BANG, MINUS]) {
+fn prefix(state: &mut State) -> Result<Expression, ParseError> {
+    if match_types(state, vec![BANG, MINUS]) {
         let operator = previous(state);
-        let right = unary(state);
-        return Expression::Unary(operator, Box::new(right));
+        let right = parse_precedence(state, PREFIX_POWER)?;
+        return Ok(Expression::Unary(operator, Box::new(right)));
+    }
+
+    if match_types(state, vec![PLUS_PLUS, MINUS_MINUS]) {
+        let operator = previous(state);
+        let target = parse_precedence(state, PREFIX_POWER)?;
+        if let Expression::Variable(name, _) = target {
+            return Ok(Expression::Update(name, operator, true, None));
+        }
+        state.errors.push(ParseError::new(operator.span, "invalid increment target"));
+        return Ok(target);
     }
 
     primary(state)
 }
 
-fn primary(state: &mut State) -> Expression {
-    if _match(state, vec![FALSE]) {
-        return Expression::Literal(previous(state));
+fn infix(state: &mut State, left: Expression, operator: Token, right_power: u8) -> Result<Expression, ParseError> {
+    match operator.token_type {
+        LEFT_PAREN => {
+            let mut arguments = Vec::new();
+            if !check(state, RIGHT_PAREN) {
+                loop {
+                    arguments.push(expression(state)?);
+                    if !match_types(state, vec![COMMA]) {
+                        break;
+                    }
+                }
+            }
+            let paren = consume(state, RIGHT_PAREN, ")")?;
+            Ok(Expression::Call(Box::new(left), paren, arguments))
+        },
+        LEFT_BRACKET => {
+            let index = expression(state)?;
+            let bracket = consume(state, RIGHT_BRACKET, "]")?;
+            Ok(Expression::Index(Box::new(left), bracket, Box::new(index)))
+        },
+        DOT => {
+            let name = consume(state, IDENTIFIER, "property name")?;
+            Ok(Expression::Get(Box::new(left), name))
+        },
+        PLUS_PLUS | MINUS_MINUS => {
+            if let Expression::Variable(name, _) = left {
+                return Ok(Expression::Update(name, operator, false, None));
+            }
+            state.errors.push(ParseError::new(operator.span, "invalid increment target"));
+            Ok(left)
+        },
+        EQUAL | PLUS_EQUAL | MINUS_EQUAL | STAR_EQUAL | SLASH_EQUAL | PERCENT_EQUAL => {
+            let value = parse_precedence(state, right_power)?;
+            match left {
+                Expression::Variable(name, _) => {
+                    if operator.token_type == EQUAL {
+                        return Ok(Expression::Assign(name, Box::new(value), None));
+                    }
+                    return Ok(Expression::CompoundAssign(name, operator, Box::new(value), None));
+                },
+                Expression::Get(object, name) if operator.token_type == EQUAL => {
+                    return Ok(Expression::Set(object, name, Box::new(value)));
+                },
+                _ => {},
+            }
+            state.errors.push(ParseError::new(operator.span, "invalid assignment target"));
+            Ok(left)
+        },
+        AND | OR => {
+            let right = parse_precedence(state, right_power)?;
+            Ok(Expression::Logical(Box::new(left), operator, Box::new(right)))
+        },
+        _ => {
+            let right = parse_precedence(state, right_power)?;
+            Ok(Expression::Binary(Box::new(left), operator, Box::new(right)))
+        },
+    }
+}
+
+fn primary(state: &mut State) -> Result<Expression, ParseError> {
+    if match_types(state, vec![FALSE]) {
+        return Ok(Expression::Literal(previous(state)));
     }
 
-    if _match(state, vec![TRUE]) {
-        return Expression::Literal(previous(state));
+    if match_types(state, vec![TRUE]) {
+        return Ok(Expression::Literal(previous(state)));
     }
     
-    if _match(state, vec![NIL]) {
-        return Expression::Literal(previous(state));
+    if match_types(state, vec![NIL]) {
+        return Ok(Expression::Literal(previous(state)));
     }
     
-    if _match(state, vec![NUMBER]) {
-        return Expression::Literal(previous(state));
+    if match_types(state, vec![NUMBER]) {
+        return Ok(Expression::Literal(previous(state)));
     }
     
-    if _match(state, vec![STRING]) {
-        return Expression::Literal(previous(state));
+    if match_types(state, vec![STRING]) {
+        return Ok(Expression::Literal(previous(state)));
     }
     
-    if _match(state, vec![IDENTIFIER]) {
-        return Expression::Variable(previous(state));
+    if match_types(state, vec![IDENTIFIER]) {
+        return Ok(Expression::Variable(previous(state), None));
+    }
+
+    if match_types(state, vec![THIS]) {
+        return Ok(Expression::This(previous(state), None));
+    }
+
+    if match_types(state, vec![SUPER]) {
+        let keyword = previous(state);
+        consume(state, DOT, ".")?;
+        let method = consume(state, IDENTIFIER, "superclass method name")?;
+        return Ok(Expression::Super(keyword, method, None));
+    }
+
+    if match_types(state, vec![FUN]) {
+        return lambda(state);
+    }
+
+    if match_types(state, vec![INTERPOLATION]) {
+        let mut parts = Vec::new();
+        loop {
+            parts.push(Expression::Literal(previous(state)));
+            parts.push(expression(state)?);
+            if !match_types(state, vec![INTERPOLATION]) {
+                break;
+            }
+        }
+        parts.push(Expression::Literal(consume(state, STRING, "end of interpolated string")?));
+        return Ok(Expression::Interpolation(parts));
     }
     
-    if _match(state, vec![LEFT_PAREN]) {
-        let expr = expression(state);
-        consume(state, RIGHT_PAREN, ")");
-        return Expression::Grouping(Box::new(expr));
+    if match_types(state, vec![LEFT_PAREN]) {
+        let expr = expression(state)?;
+        consume(state, RIGHT_PAREN, ")")?;
+        return Ok(Expression::Grouping(Box::new(expr)));
     }
-    let token = &state.tokens[state.current];
-    error_handling::error(token.line, token.index, "expected expression");
-    Expression::Literal(Token::new(NIL, "".to_string(), vec![], token.line, token.index))
+    Err(ParseError::new(peek(state).span, "expected expression"))
 }
 
-fn consume(state: &mut State, token_type: TokenType, expected: &str) -> Token {
+fn consume(state: &mut State, token_type: TokenType, expected: &str) -> Result<Token, ParseError> {
     if check(state, token_type) {
-        advance(state)
+        Ok(advance(state))
     } else {
-        let token = &state.tokens[state.current];
-        error_handling::error(token.line, token.index, &("expecting '".to_owned() + expected + "'"));
-        advance(state)
+        Err(ParseError::new(peek(state).span, &("expecting '".to_owned() + expected + "'")))
     }
 }
 
+fn is_newline(token: &Token) -> bool {
+    token.token_type == SEMICOLON && token.lexeme == "\n"
+}
+
+fn skip_newlines(state: &mut State) {
+    while is_newline(&state.tokens[state.current]) {
+        state.current += 1;
+    }
+}
+
+fn terminator(state: &mut State) -> Result<(), ParseError> {
+    if !match_types(state, vec![SEMICOLON]) && !check(state, RIGHT_BRACE) && !check(state, ELSE) && !is_at_end(state) {
+        consume(state, SEMICOLON, "a semicolon or a line break")?;
+    }
+    Ok(())
+}
+
 fn synchronize(state: &mut State) {
-    advance(state);
-    
+    if !matches!(peek(state).token_type, RIGHT_BRACE | CLASS | FUN | VAR | FOR | IF | WHILE | PRINT | RETURN) {
+        advance(state);
+    }
+
     while !is_at_end(state) {
-        if check(state, SEMICOLON) {
+        if check(state, SEMICOLON) || check(state, RIGHT_BRACE) {
             return
         }
 
@@ -207,11 +632,9 @@ fn synchronize(state: &mut State) {
             _ => {advance(state);},
         }
     }
-
-    return
 }
 
-fn _match(state: &mut State, types: Vec<TokenType>) -> bool {
+fn match_types(state: &mut State, types: Vec<TokenType>) -> bool {
     for token_type in types {
         if check(state, token_type) {
             advance(state);
@@ -247,4 +670,93 @@ fn peek(state: &mut State) -> Token {
 
 fn previous(state: &mut State) -> Token {
     state.tokens[state.current - 1].clone()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::scan;
+
+    fn parse_source(source: &str) -> String {
+        let tokens = scan(source, false).expect("source lexes");
+        match parse(tokens) {
+            Ok(statements) => print(&statements, false),
+            Err(errors) => panic!("{source:?} failed to parse: {errors:?}"),
+        }
+    }
+
+    #[test]
+    fn binary_operator_at_end_of_line() {
+        assert_eq!(parse_source("let x = 1 +\n  2\nprint x"), "(let x (+ 1 2))\n(print x)\n");
+    }
+
+    #[test]
+    fn parenthesised_initializer_spanning_lines() {
+        assert_eq!(parse_source("let y = (1\n  + 2\n)\nprint y"), "(let y (group (+ 1 2)))\n(print y)\n");
+    }
+
+    #[test]
+    fn closing_brace_then_else_on_the_next_line() {
+        assert_eq!(
+            parse_source("if (a) {\n  print 1\n}\nelse {\n  print 2\n}\nprint 3"),
+            "(if a\n  (block\n    (print 1))\n  (block\n    (print 2)))\n(print 3)\n",
+        );
+    }
+
+    #[test]
+    fn newlines_inside_call_arguments() {
+        assert_eq!(parse_source("f(1,\n  2\n)\nprint 3"), "(call f 1 2)\n(print 3)\n");
+    }
+
+    #[test]
+    fn return_followed_by_a_newline_returns_nothing() {
+        assert_eq!(parse_source("fn f() {\n  return\n  1\n}"), "(fn f ()\n  (return)\n  1)\n");
+    }
+
+    #[test]
+    fn explicit_semicolons_mixed_with_newlines() {
+        assert_eq!(parse_source("print 1; print 2\nprint 3;\n;print 4;;"), "(print 1)\n(print 2)\n(print 3)\n(print 4)\n");
+    }
+
+    #[test]
+    fn declarations_can_be_followed_on_the_same_line() {
+        assert_eq!(parse_source("class A {} print 1;"), "(class A)\n(print 1)\n");
+        assert_eq!(parse_source("fn f() {} print 1"), "(fn f ())\n(print 1)\n");
+        assert_eq!(parse_source("class A {}\nprint 1"), "(class A)\n(print 1)\n");
+    }
+
+    #[test]
+    fn newline_after_a_complete_expression_ends_the_statement() {
+        assert_eq!(parse_source("let a = b\n(c)"), "(let a b)\n(group c)\n");
+    }
+
+    #[test]
+    fn arithmetic_associates_left() {
+        assert_eq!(parse_source("print a * b * c"), "(print (* (* a b) c))\n");
+        assert_eq!(parse_source("print a - b - c"), "(print (- (- a b) c))\n");
+        assert_eq!(parse_source("print a - b * c / d"), "(print (- a (/ (* b c) d)))\n");
+    }
+
+    #[test]
+    fn assignment_associates_right() {
+        assert_eq!(parse_source("a = b = 3"), "(= a (= b 3))\n");
+        assert_eq!(parse_source("o.x = p.y = 1"), "(= (. o x) (= (. p y) 1))\n");
+    }
+
+    #[test]
+    fn calls_indexing_and_properties_chain_as_postfix() {
+        assert_eq!(parse_source("print f(1)(2)"), "(print (call (call f 1) 2))\n");
+        assert_eq!(parse_source("print a.b(1).c[0]"), "(print ([] (. (call (. a b) 1) c) 0))\n");
+        assert_eq!(parse_source("print -a.b[1]"), "(print (- ([] (. a b) 1)))\n");
+    }
+
+
+    #[test]
+    fn statement_spans_cover_every_token() {
+        let source = "let a = 1\nif (a) {\n  print a\n} else print -a\nfn f(x) {\n  return x\n}\n{}";
+        let statements = parse(scan(source, false).expect("source lexes")).expect("source parses");
+        let spans: Vec<String> = statements.iter().map(|statement| statement.span().map_or("none".to_string(), |span| span.to_string())).collect();
+        assert_eq!(spans, ["[1:5] to [1:10]", "[2:5] to [4:16]", "[5:4] to [6:11]", "none"]);
+    }
+
+}
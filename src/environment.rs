@@ -1,55 +1,306 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
-use crate::{token::Token, error_handling::error};
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::{token::{Span, Token, TokenType}, error_handling::error, interpreter::Unwind, ast::ParseError};
+/// Variable references end with the slot filled in by the resolver, or `None`
+/// for a global.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "fields")]
 pub enum Expression {
     Literal(Token),
     Unary(Token, Box<Expression>),
     Binary(Box<Expression>, Token, Box<Expression>),
+    Logical(Box<Expression>, Token, Box<Expression>),
     Grouping(Box<Expression>),
-    Variable(Token)
+    Variable(Token, #[serde(skip)] Option<Slot>),
+    Interpolation(Vec<Expression>),
+    Call(Box<Expression>, Token, Vec<Expression>),
+    Assign(Token, Box<Expression>, #[serde(skip)] Option<Slot>),
+    Lambda(Rc<FunctionDeclaration>),
+    CompoundAssign(Token, Token, Box<Expression>, #[serde(skip)] Option<Slot>),
+    Update(Token, Token, bool, #[serde(skip)] Option<Slot>),
+    Get(Box<Expression>, Token),
+    Index(Box<Expression>, Token, Box<Expression>),
+    Set(Box<Expression>, Token, Box<Expression>),
+    This(Token, #[serde(skip)] Option<Slot>),
+    Super(Token, Token, #[serde(skip)] Option<Slot>),
 }
 
-#[derive(Debug, Clone)]
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Literal(token) | Expression::Variable(token, _) | Expression::This(token, _) => token.span,
+            Expression::Unary(op, right) => op.span.to(right.span()),
+            Expression::Binary(left, _, right) | Expression::Logical(left, _, right) => left.span().to(right.span()),
+            Expression::Grouping(expr) => expr.span(),
+            Expression::Interpolation(parts) => parts[0].span().to(parts[parts.len() - 1].span()),
+            Expression::Call(callee, paren, _) => callee.span().to(paren.span),
+            Expression::Assign(name, value, _) | Expression::CompoundAssign(name, _, value, _) => name.span.to(value.span()),
+            Expression::Update(name, op, _, _) => name.span.to(op.span),
+            Expression::Lambda(declaration) => declaration.span(),
+            Expression::Get(object, name) => object.span().to(name.span),
+            Expression::Index(object, bracket, _) => object.span().to(bracket.span),
+            Expression::Set(object, _, value) => object.span().to(value.span()),
+            Expression::Super(keyword, method, _) => keyword.span.to(method.span),
+        }
+    }
+}
+
+/// Where the resolver found a local: how many environments out from the use,
+/// and its index in that environment's slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "fields")]
 pub enum Statement {
     Expression(Expression),
     Print(Expression),
-    Var(Token, Expression)
+    Var(Token, Expression),
+    Block(Vec<Statement>),
+    If(Expression, Box<Statement>, Option<Box<Statement>>),
+    While(Expression, Box<Statement>),
+    Function(Rc<FunctionDeclaration>),
+    Return(Token, Option<Expression>),
+    Class(Token, Option<Expression>, Vec<Rc<FunctionDeclaration>>),
+}
+
+impl Statement {
+    /// Covers every token the statement holds, so `None` only for an empty block.
+    pub fn span(&self) -> Option<Span> {
+        let span = match self {
+            Statement::Expression(expr) | Statement::Print(expr) => expr.span(),
+            Statement::Var(name, initializer) => name.span.to(initializer.span()),
+            Statement::Block(statements) => return statements.iter().filter_map(Statement::span).reduce(Span::to),
+            Statement::If(condition, then_branch, else_branch) => {
+                let branches = std::iter::once(then_branch).chain(else_branch);
+                branches.filter_map(|branch| branch.span()).fold(condition.span(), Span::to)
+            },
+            Statement::While(condition, body) => body.span().map_or(condition.span(), |body| condition.span().to(body)),
+            Statement::Function(declaration) => declaration.span(),
+            Statement::Return(keyword, value) => value.as_ref().map_or(keyword.span, |value| keyword.span.to(value.span())),
+            Statement::Class(name, superclass, methods) => {
+                let superclass = superclass.iter().map(Expression::span);
+                superclass.chain(methods.iter().map(|method| method.span())).fold(name.span, Span::to)
+            },
+        };
+        Some(span)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionDeclaration {
+    /// The function's identifier, or the `fn` keyword for a lambda.
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Statement>,
+}
+
+impl FunctionDeclaration {
+    pub fn span(&self) -> Span {
+        let params = self.params.iter().map(|param| param.span);
+        params.chain(self.body.iter().filter_map(Statement::span)).fold(self.name.span, Span::to)
+    }
 }
 
 pub struct State {
     pub tokens: Vec<Token>,
     pub current: usize,
+    pub errors: Vec<ParseError>,
 }
 #[derive(Debug, Clone)]
 pub enum Value {
     Number(f64),
     String(String),
     Boolean(bool),
+    Native(Native),
+    Function(Rc<Function>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
     Null,
 }
 
+#[derive(Debug, Clone)]
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&Token, &[Value]) -> Result<Value, Unwind>,
+}
+
+pub struct Function {
+    pub declaration: Rc<FunctionDeclaration>,
+    pub closure: Rc<RefCell<Environment>>,
+    pub is_initializer: bool,
+}
+
+impl Function {
+    pub fn bind(&self, instance: &Rc<RefCell<Instance>>) -> Function {
+        let mut scope = Environment::new_enclosed(Rc::clone(&self.closure));
+        scope.define("this", Value::Instance(Rc::clone(instance)));
+        Function {
+            declaration: Rc::clone(&self.declaration),
+            closure: Rc::new(RefCell::new(scope)),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<Rc<Class>>,
+    pub methods: HashMap<String, Rc<Function>>,
+}
+
+impl Class {
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref().and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<String, Value>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function").field("name", &self.declaration.name.lexeme).finish_non_exhaustive()
+    }
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Null | Value::Boolean(false))
+    }
+
+    #[allow(clippy::float_cmp)]
+    pub fn is_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Native(a), Value::Native(b)) => a.name == b.name,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Boolean(b) => write!(f, "{b}"),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Function(function) => {
+                let name = &function.declaration.name;
+                if name.token_type == TokenType::IDENTIFIER {
+                    write!(f, "<fn {}>", name.lexeme)
+                } else {
+                    write!(f, "<fn>")
+                }
+            },
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "<{} instance>", instance.borrow().class.name),
+            Value::Null => write!(f, "null"),
+        }
+    }
+}
+
+/// The global environment keeps its variables by name so the REPL can add to
+/// them; every enclosed one stores locals in `slots`, numbered by the resolver
+/// in declaration order, and holds the global one directly so unresolved names
+/// don't walk the frames in between.
 pub struct Environment {
     values: HashMap<String, Value>,
+    slots: Vec<Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    globals: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Environment {
             values: HashMap::new(),
+            slots: Vec::new(),
+            enclosing: None,
+            globals: None,
         }
     }
 
-    pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+    pub fn new_enclosed(enclosing: Rc<RefCell<Environment>>) -> Self {
+        let globals = enclosing.borrow().globals.clone().unwrap_or_else(|| Rc::clone(&enclosing));
+        Environment {
+            values: HashMap::new(),
+            slots: Vec::new(),
+            enclosing: Some(enclosing),
+            globals: Some(globals),
+        }
     }
 
-    pub fn get(&self, token: &Token) -> Value {
-        if !self.values.contains_key(token.lexeme.as_str()) {
-            error(token.line, token.index, &("undefined variable '".to_owned() + token.lexeme.as_str() + "'"));
-            return Value::Null;
+    pub fn define(&mut self, name: &str, value: Value) {
+        if self.enclosing.is_some() {
+            self.slots.push(value);
+        } else {
+            self.values.insert(name.to_string(), value);
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.values.keys()
+    }
+
+    pub fn get_at(&self, slot: Slot) -> Value {
+        if slot.depth == 0 {
+            return self.slots[slot.index].clone();
+        }
+        self.enclosing.as_ref().expect("resolved depth within the scope chain").borrow().get_at(Slot { depth: slot.depth - 1, ..slot })
+    }
+
+    pub fn assign_at(&mut self, slot: Slot, value: Value) {
+        if slot.depth == 0 {
+            self.slots[slot.index] = value;
+            return;
+        }
+        self.enclosing.as_ref().expect("resolved depth within the scope chain").borrow_mut().assign_at(Slot { depth: slot.depth - 1, ..slot }, value);
+    }
+
+    pub fn get(&self, token: &Token) -> Result<Value, Unwind> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow().get(token);
+        }
+        if let Some(val) = self.values.get(token.lexeme.as_str()) {
+            return Ok(val.clone());
+        }
+        error(token.span, &("undefined variable '".to_owned() + token.lexeme.as_str() + "'"));
+        Err(Unwind::Error)
+    }
+
+    pub fn assign(&mut self, token: &Token, value: Value) -> Result<(), Unwind> {
+        if let Some(globals) = &self.globals {
+            return globals.borrow_mut().assign(token, value);
+        }
+        if let Some(slot) = self.values.get_mut(token.lexeme.as_str()) {
+            *slot = value;
+            return Ok(());
         }
-        let val = self.values.get(token.lexeme.as_str()).unwrap();
-        val.clone()
+        error(token.span, &("undefined variable '".to_owned() + token.lexeme.as_str() + "'"));
+        Err(Unwind::Error)
     }
 }
\ No newline at end of file
@@ -1,13 +1,9 @@
-pub fn error(line: usize, index: usize, msg: &str) {
-    println!(
-        "\x1b[1;31merror: \x1b[0m{} on line [{}:{}]",
-        msg, line, index
-    );
+use crate::token::Span;
+
+pub fn error(span: Span, msg: &str) {
+    println!("\x1b[1;31merror: \x1b[0m{msg} on line {span}");
 }
 
-pub fn warning(line: usize, index: usize, msg: &str) {
-    println!(
-        "\x1b[1;33mwarning: \x1b[0m{} on line [{}:{}]",
-        msg, line, index
-    );
+pub fn warning(span: Span, msg: &str) {
+    println!("\x1b[1;33mwarning: \x1b[0m{msg} on line {span}");
 }
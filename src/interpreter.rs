@@ -1,93 +1,411 @@
-use crate::{token::{TokenType}, error_handling::error};
-use crate::environment::*;
-
-fn print(expr: &Expression, env: &Environment) {
-    match evaluate(expr, env) {
-        Value::Number(n) => println!("{}", n),
-        Value::String(s) => println!("{}", s),
-        Value::Boolean(b) => println!("{}", b),
-        Value::Null => println!("null"),
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::{token::{Literal, Span, Token, TokenType}, error_handling::error};
+use crate::environment::{Class, Environment, Expression, Function, FunctionDeclaration, Instance, Native, Slot, Statement, Value};
+
+/// Non-local exits that unwind through `execute` and `evaluate`.
+#[derive(Debug, Clone)]
+pub enum Unwind {
+    Exit(u8),
+    Return(Value),
+    /// A runtime error that has already been reported; stops the program.
+    Error,
+}
+
+/// How many calls may be in progress at once before the program is stopped,
+/// well before the native stack would overflow.
+const MAX_CALL_DEPTH: usize = 1000;
+
+thread_local! {
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn define_natives(env: &mut Environment) {
+    env.define("exit", Value::Native(Native { name: "exit", arity: 1, function: exit }));
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn exit(paren: &Token, args: &[Value]) -> Result<Value, Unwind> {
+    match args[0] {
+        Value::Number(code) if code.fract() == 0.0 && (0.0..=255.0).contains(&code) => Err(Unwind::Exit(code as u8)),
+        _ => {
+            error(paren.span, "exit code must be an integer from 0 to 255");
+            Err(Unwind::Error)
+        },
     }
 }
 
-pub fn execute(statements: &Vec<Statement>, env: &mut Environment) {
+fn call(function: &Function, args: Vec<Value>) -> Result<Value, Unwind> {
+    let mut scope = Environment::new_enclosed(Rc::clone(&function.closure));
+    for (param, arg) in function.declaration.params.iter().zip(args) {
+        scope.define(&param.lexeme, arg);
+    }
+
+    let value = match execute(&function.declaration.body, &Rc::new(RefCell::new(scope))) {
+        Ok(()) => Value::Null,
+        Err(Unwind::Return(value)) => value,
+        Err(unwind) => return Err(unwind),
+    };
+
+    if function.is_initializer {
+        return Ok(function.closure.borrow().get_at(Slot { depth: 0, index: 0 }));
+    }
+    Ok(value)
+}
+
+fn instantiate(class: &Rc<Class>, args: Vec<Value>) -> Result<Value, Unwind> {
+    let instance = Rc::new(RefCell::new(Instance { class: Rc::clone(class), fields: HashMap::new() }));
+    if let Some(initializer) = class.find_method("init") {
+        call(&initializer.bind(&instance), args)?;
+    }
+    Ok(Value::Instance(instance))
+}
+
+fn class(name: &Token, superclass: Option<&Expression>, methods: &[Rc<FunctionDeclaration>], env: &Rc<RefCell<Environment>>) -> Result<(), Unwind> {
+    let superclass = match superclass {
+        Some(expr) => {
+            if let Value::Class(superclass) = evaluate(expr, env)? {
+                Some(superclass)
+            } else {
+                error(expr.span(), "superclass must be a class");
+                return Err(Unwind::Error);
+            }
+        },
+        None => None,
+    };
+
+    let closure = match &superclass {
+        Some(superclass) => {
+            let mut scope = Environment::new_enclosed(Rc::clone(env));
+            scope.define("super", Value::Class(Rc::clone(superclass)));
+            Rc::new(RefCell::new(scope))
+        },
+        None => Rc::clone(env),
+    };
+
+    let mut table = HashMap::new();
+    for method in methods {
+        let function = Function {
+            declaration: Rc::clone(method),
+            closure: Rc::clone(&closure),
+            is_initializer: method.name.lexeme == "init",
+        };
+        table.insert(method.name.lexeme.clone(), Rc::new(function));
+    }
+
+    let class = Class { name: name.lexeme.clone(), superclass, methods: table };
+    env.borrow_mut().define(&name.lexeme, Value::Class(Rc::new(class)));
+    Ok(())
+}
+
+fn lookup(env: &Rc<RefCell<Environment>>, name: &Token, slot: Option<Slot>) -> Result<Value, Unwind> {
+    match slot {
+        Some(slot) => Ok(env.borrow().get_at(slot)),
+        None => env.borrow().get(name),
+    }
+}
+
+fn store(env: &Rc<RefCell<Environment>>, name: &Token, slot: Option<Slot>, value: Value) -> Result<(), Unwind> {
+    match slot {
+        Some(slot) => {
+            env.borrow_mut().assign_at(slot, value);
+            Ok(())
+        },
+        None => env.borrow_mut().assign(name, value),
+    }
+}
+
+fn print(expr: &Expression, env: &Rc<RefCell<Environment>>) -> Result<(), Unwind> {
+    println!("{}", evaluate(expr, env)?);
+    Ok(())
+}
+
+pub fn execute(statements: &[Statement], env: &Rc<RefCell<Environment>>) -> Result<(), Unwind> {
     for statement in statements {
-        match statement {
-            Statement::Expression(expr) => { evaluate(&expr, env); () },
-            Statement::Print(expr) => { print(&expr, env); () },
-            Statement::Var(token, expr) => {
-                let val = evaluate(&expr, env);
-                env.define(token.lexeme.clone(), val);
-                ()
+        execute_statement(statement, env)?;
+    }
+
+    Ok(())
+}
+
+fn execute_statement(statement: &Statement, env: &Rc<RefCell<Environment>>) -> Result<(), Unwind> {
+    match statement {
+        Statement::Expression(expr) => { evaluate(expr, env)?; },
+        Statement::Print(expr) => print(expr, env)?,
+        Statement::Var(token, expr) => {
+            let val = evaluate(expr, env)?;
+            env.borrow_mut().define(&token.lexeme, val);
+        }
+        Statement::Block(statements) => {
+            let scope = Rc::new(RefCell::new(Environment::new_enclosed(Rc::clone(env))));
+            execute(statements, &scope)?;
+        }
+        Statement::If(condition, then_branch, else_branch) => {
+            if evaluate(condition, env)?.is_truthy() {
+                execute_statement(then_branch, env)?;
+            } else if let Some(else_branch) = else_branch {
+                execute_statement(else_branch, env)?;
             }
         }
+        Statement::While(condition, body) => {
+            while evaluate(condition, env)?.is_truthy() {
+                execute_statement(body, env)?;
+            }
+        }
+        Statement::Function(declaration) => {
+            let function = Function { declaration: Rc::clone(declaration), closure: Rc::clone(env), is_initializer: false };
+            env.borrow_mut().define(&declaration.name.lexeme, Value::Function(Rc::new(function)));
+        }
+        Statement::Return(_, value) => {
+            let value = match value {
+                Some(value) => evaluate(value, env)?,
+                None => Value::Null,
+            };
+            return Err(Unwind::Return(value));
+        }
+        Statement::Class(name, superclass, methods) => class(name, superclass.as_ref(), methods, env)?,
     }
+
+    Ok(())
 }
 
-pub fn evaluate(expr: &Expression, env: &Environment) -> Value {
-    match expr {
-        Expression::Binary(left, op, right) => {
-            let left = evaluate(&*left, env);
-            let right = evaluate(&*right, env);
-
-            match (left, right) {
-                (Value::Number(left), Value::Number(right)) => {
-                    match op.token_type {
-                        TokenType::PLUS => Value::Number(left + right),
-                        TokenType::MINUS => Value::Number(left - right),
-                        TokenType::SLASH => Value::Number(left / right),
-                        TokenType::STAR => Value::Number(left * right),
-                        TokenType::GREATER => Value::Boolean(left > right),
-                        TokenType::GREATER_EQUAL => Value::Boolean(left >= right),
-                        TokenType::LESS => Value::Boolean(left < right),
-                        TokenType::LESS_EQUAL => Value::Boolean(left <= right),
-                        TokenType::BANG_EQUAL => Value::Boolean(left != right),
-                        TokenType::EQUAL_EQUAL => Value::Boolean(left == right),
-                        _ => {error(op.line, op.index, "invalid operator"); Value::Null},
-                    }
-                }
-                (Value::String(left), Value::String(right)) => {
-                    match op.token_type {
-                        TokenType::PLUS => Value::String(left.clone() + &right),
-                        _ => {error(op.line, op.index, "invalid operator"); Value::String("".to_string())},
-                    }
+fn binary(operator: TokenType, op: &Token, left: Value, right: Value, span: Span) -> Result<Value, Unwind> {
+    match operator {
+        TokenType::EQUAL_EQUAL => return Ok(Value::Boolean(left.is_equal(&right))),
+        TokenType::BANG_EQUAL => return Ok(Value::Boolean(!left.is_equal(&right))),
+        _ => {},
+    }
+
+    let value = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => {
+            match operator {
+                TokenType::PLUS => Value::Number(left + right),
+                TokenType::MINUS => Value::Number(left - right),
+                TokenType::SLASH => Value::Number(left / right),
+                TokenType::STAR => Value::Number(left * right),
+                TokenType::PERCENT => Value::Number(left % right),
+                TokenType::GREATER => Value::Boolean(left > right),
+                TokenType::GREATER_EQUAL => Value::Boolean(left >= right),
+                TokenType::LESS => Value::Boolean(left < right),
+                TokenType::LESS_EQUAL => Value::Boolean(left <= right),
+                _ => {
+                    error(op.span, "invalid operator");
+                    return Err(Unwind::Error);
                 },
-                _ => {error(op.line, op.index, "invalid operands"); Value::Null},
             }
         }
-        Expression::Grouping(expr) => evaluate(&*expr, env),
+        (Value::String(left), Value::String(right)) if operator == TokenType::PLUS => Value::String(left + &right),
+        (Value::String(_), Value::String(_)) => {
+            error(op.span, "invalid operator");
+            return Err(Unwind::Error);
+        },
+        _ => {
+            error(span, "invalid operands");
+            return Err(Unwind::Error);
+        },
+    };
+    Ok(value)
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn subscript(object: &Value, index: &Value, bracket: &Token) -> Result<Value, Unwind> {
+    match (object, index) {
+        (Value::String(text), Value::Number(n)) => {
+            if n.fract() != 0.0 || *n < 0.0 {
+                error(bracket.span, "string index must be a non-negative integer");
+                return Err(Unwind::Error);
+            }
+            if let Some(c) = text.chars().nth(*n as usize) {
+                Ok(Value::String(c.to_string()))
+            } else {
+                error(bracket.span, &format!("index {} out of range for string of length {}", n, text.chars().count()));
+                Err(Unwind::Error)
+            }
+        },
+        (Value::String(_), _) => {
+            error(bracket.span, "string index must be a number");
+            Err(Unwind::Error)
+        },
+        _ => {
+            error(bracket.span, "can only index strings");
+            Err(Unwind::Error)
+        },
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn evaluate(expr: &Expression, env: &Rc<RefCell<Environment>>) -> Result<Value, Unwind> {
+    let value = match expr {
+        Expression::Binary(left, op, right) => {
+            let left = evaluate(left, env)?;
+            let right = evaluate(right, env)?;
+            binary(op.token_type, op, left, right, expr.span())?
+        }
+        Expression::Logical(left, op, right) => {
+            let left = evaluate(left, env)?;
+
+            if (op.token_type == TokenType::OR) == left.is_truthy() {
+                left
+            } else {
+                evaluate(right, env)?
+            }
+        }
+        Expression::Grouping(expr) => evaluate(expr, env)?,
         Expression::Literal(value) => {
-            match value.token_type {
-                TokenType::STRING => Value::String(value.lexeme.clone()),
-                TokenType::NUMBER => Value::Number(value.lexeme.parse::<f64>().unwrap()),
-                TokenType::TRUE => Value::Boolean(true),
-                TokenType::FALSE => Value::Boolean(false),
-                TokenType::NIL => Value::Null,
-                _ => {error(value.line, value.index, "invalid literal"); Value::Null},
+            match (value.token_type, &value.literal) {
+                (TokenType::STRING | TokenType::INTERPOLATION, Literal::String(s)) => Value::String(s.clone()),
+                (TokenType::NUMBER, Literal::Number(n)) => Value::Number(*n),
+                (TokenType::TRUE, _) => Value::Boolean(true),
+                (TokenType::FALSE, _) => Value::Boolean(false),
+                (TokenType::NIL, _) => Value::Null,
+                _ => {
+                    error(value.span, "invalid literal");
+                    return Err(Unwind::Error);
+                },
             }
         },
         Expression::Unary(op, expr) => {
-            let right = evaluate(&*expr, env);
-
-            match right {
-                Value::Number(right) => {
-                    match op.token_type {
-                        TokenType::MINUS => Value::Number(-right),
-                        _ => {error(op.line, op.index, "invalid operator"); Value::Null},
-                    }
-                },
-                Value::Boolean(right) => {
-                    match op.token_type {
-                        TokenType::BANG => Value::Boolean(!right),
-                        _ => {error(op.line, op.index, "invalid operator"); Value::Null},
-                    }
+            let right = evaluate(expr, env)?;
+
+            match (op.token_type, right) {
+                (TokenType::BANG, right) => Value::Boolean(!right.is_truthy()),
+                (TokenType::MINUS, Value::Number(right)) => Value::Number(-right),
+                _ => {
+                    error(op.span, "invalid operands");
+                    return Err(Unwind::Error);
                 },
-                _ => {error(op.line, op.index, "invalid operands"); Value::Null},
             }
         },
-        Expression::Variable(token) => {
-            let val = env.get(&token);
-            val.clone()
+        Expression::Variable(token, slot) => lookup(env, token, *slot)?,
+        Expression::Interpolation(parts) => {
+            let mut text = String::new();
+            for part in parts {
+                text += &evaluate(part, env)?.to_string();
+            }
+            Value::String(text)
         }
-    }
-}
\ No newline at end of file
+        Expression::Assign(name, value, slot) => {
+            let value = evaluate(value, env)?;
+            store(env, name, *slot, value.clone())?;
+            value
+        }
+        Expression::CompoundAssign(name, op, value, slot) => {
+            let operator = match op.token_type {
+                TokenType::PLUS_EQUAL => TokenType::PLUS,
+                TokenType::MINUS_EQUAL => TokenType::MINUS,
+                TokenType::STAR_EQUAL => TokenType::STAR,
+                TokenType::SLASH_EQUAL => TokenType::SLASH,
+                _ => TokenType::PERCENT,
+            };
+            let current = lookup(env, name, *slot)?;
+            let value = evaluate(value, env)?;
+            let result = binary(operator, op, current, value, expr.span())?;
+            store(env, name, *slot, result.clone())?;
+            result
+        }
+        Expression::Update(name, op, prefix, slot) => {
+            let current = lookup(env, name, *slot)?;
+            if let Value::Number(current) = current {
+                let updated = if op.token_type == TokenType::PLUS_PLUS { current + 1.0 } else { current - 1.0 };
+                store(env, name, *slot, Value::Number(updated))?;
+                Value::Number(if *prefix { updated } else { current })
+            } else {
+                error(expr.span(), "can only increment or decrement numbers");
+                return Err(Unwind::Error);
+            }
+        }
+        Expression::Lambda(declaration) => {
+            Value::Function(Rc::new(Function { declaration: Rc::clone(declaration), closure: Rc::clone(env), is_initializer: false }))
+        }
+        Expression::Call(callee, paren, arguments) => {
+            let function = evaluate(callee, env)?;
+
+            let mut args = Vec::new();
+            for argument in arguments {
+                args.push(evaluate(argument, env)?);
+            }
+
+            let arity = match &function {
+                Value::Native(native) => native.arity,
+                Value::Function(function) => function.declaration.params.len(),
+                Value::Class(class) => class.find_method("init").map_or(0, |init| init.declaration.params.len()),
+                _ => {
+                    error(callee.span(), "can only call functions");
+                    return Err(Unwind::Error);
+                },
+            };
+            if args.len() != arity {
+                error(paren.span, &format!("expected {} arguments but got {}", arity, args.len()));
+                return Err(Unwind::Error);
+            }
+
+            let depth = CALL_DEPTH.get();
+            if depth == MAX_CALL_DEPTH {
+                error(paren.span, "stack overflow");
+                return Err(Unwind::Error);
+            }
+            CALL_DEPTH.set(depth + 1);
+            let result = match function {
+                Value::Native(native) => (native.function)(paren, &args),
+                Value::Function(function) => call(&function, args),
+                Value::Class(class) => instantiate(&class, args),
+                _ => Ok(Value::Null),
+            };
+            CALL_DEPTH.set(depth);
+            result?
+        }
+        Expression::Get(object, name) => {
+            if let Value::Instance(instance) = evaluate(object, env)? {
+                let field = instance.borrow().fields.get(&name.lexeme).cloned();
+                let method = instance.borrow().class.find_method(&name.lexeme);
+                match (field, method) {
+                    (Some(value), _) => value,
+                    (None, Some(method)) => Value::Function(Rc::new(method.bind(&instance))),
+                    (None, None) => {
+                        error(name.span, &("undefined property '".to_string() + &name.lexeme + "'"));
+                        return Err(Unwind::Error);
+                    },
+                }
+            } else {
+                error(object.span(), "only instances have properties");
+                return Err(Unwind::Error);
+            }
+        }
+        Expression::Index(object, bracket, index) => {
+            let object = evaluate(object, env)?;
+            let index = evaluate(index, env)?;
+            subscript(&object, &index, bracket)?
+        }
+        Expression::Set(object, name, value) => {
+            if let Value::Instance(instance) = evaluate(object, env)? {
+                let value = evaluate(value, env)?;
+                instance.borrow_mut().fields.insert(name.lexeme.clone(), value.clone());
+                value
+            } else {
+                error(object.span(), "only instances have fields");
+                return Err(Unwind::Error);
+            }
+        }
+        Expression::This(keyword, slot) => lookup(env, keyword, *slot)?,
+        Expression::Super(keyword, method, slot) => {
+            let this = Token::new(TokenType::THIS, "this".to_string(), Literal::None, keyword.span);
+            let superclass = lookup(env, keyword, *slot)?;
+            let object = lookup(env, &this, slot.map(|slot| Slot { depth: slot.depth - 1, index: 0 }))?;
+            if let (Value::Class(superclass), Value::Instance(instance)) = (superclass, object) {
+                if let Some(found) = superclass.find_method(&method.lexeme) {
+                    Value::Function(Rc::new(found.bind(&instance)))
+                } else {
+                    error(method.span, &("undefined property '".to_string() + &method.lexeme + "'"));
+                    return Err(Unwind::Error);
+                }
+            } else {
+                error(keyword.span, "can't use 'super' outside of a subclass method");
+                return Err(Unwind::Error);
+            }
+        }
+    };
+
+    Ok(value)
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{token::{Literal, Token}, environment::{Expression, Statement}};
+use crate::visit::Visitor;
+
+/// Bumped whenever the JSON shape of `Token`, `Expression` or `Statement` changes.
+pub const SCHEMA_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct TokenDocument {
+    version: u32,
+    tokens: Vec<Token>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AstDocument {
+    version: u32,
+    statements: Vec<Statement>,
+}
+
+pub fn tokens_to_json(tokens: &[Token]) -> String {
+    let document = TokenDocument { version: SCHEMA_VERSION, tokens: tokens.to_vec() };
+    serde_json::to_string_pretty(&document).expect("tokens always serialize")
+}
+
+pub fn ast_to_json(statements: &[Statement]) -> String {
+    let document = AstDocument { version: SCHEMA_VERSION, statements: statements.to_vec() };
+    serde_json::to_string_pretty(&document).expect("statements always serialize")
+}
+
+pub fn ast_from_json(source: &str) -> Result<Vec<Statement>, String> {
+    let document: AstDocument = serde_json::from_str(source).map_err(|err| err.to_string())?;
+    if document.version != SCHEMA_VERSION {
+        return Err(format!("unsupported AST schema version {} (expected {SCHEMA_VERSION})", document.version));
+    }
+
+    let mut validator = Validator { error: None };
+    for statement in &document.statements {
+        validator.visit_statement(statement);
+    }
+    match validator.error {
+        Some(error) => Err(error),
+        None => Ok(document.statements),
+    }
+}
+
+/// Rejects shapes `ast::parse` never produces, which the resolver and
+/// interpreter rely on not seeing.
+struct Validator {
+    error: Option<String>,
+}
+
+impl Validator {
+    fn report(&mut self, message: String) {
+        self.error.get_or_insert(message);
+    }
+
+    /// A declaration takes a slot in the enclosing scope, so it can't be the
+    /// lone body of an `if` or `while` that might not run it.
+    fn branch(&mut self, statement: &Statement) {
+        let name = match statement {
+            Statement::Var(name, _) | Statement::Class(name, _, _) => name,
+            Statement::Function(declaration) => &declaration.name,
+            _ => return self.visit_statement(statement),
+        };
+        let span = statement.span().unwrap_or(name.span);
+        self.report(format!("declaration of '{}' at {span} must be at the top level or directly in a block", name.lexeme));
+    }
+}
+
+impl Visitor for Validator {
+    fn visit_if(&mut self, condition: &Expression, then_branch: &Statement, else_branch: Option<&Statement>) {
+        self.visit_expression(condition);
+        self.branch(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.branch(else_branch);
+        }
+    }
+
+    fn visit_while(&mut self, condition: &Expression, body: &Statement) {
+        self.visit_expression(condition);
+        self.branch(body);
+    }
+
+    fn visit_interpolation(&mut self, parts: &[Expression]) {
+        let strings = parts.iter().step_by(2).all(|part| matches!(part, Expression::Literal(Token { literal: Literal::String(_), .. })));
+        if parts.len().is_multiple_of(2) || !strings {
+            self.report("interpolation parts must alternate string literals and expressions, starting and ending with a string".to_string());
+        }
+        for part in parts {
+            self.visit_expression(part);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{ast, scanner::scan};
+
+    fn document(source: &str) -> Value {
+        let statements = ast::parse(scan(source, false).expect("source lexes")).expect("source parses");
+        serde_json::from_str(&ast_to_json(&statements)).expect("valid JSON")
+    }
+
+    fn load(document: &Value) -> Result<Vec<Statement>, String> {
+        ast_from_json(&document.to_string())
+    }
+
+    #[test]
+    fn round_trips_what_the_parser_produces() {
+        let source = "class A < B { init(x) { this.x = x } }\nfor (let i = 0; i < 3; i++) print \"${i}!\"\nlet f = fn(a) => a[0]\n";
+        let statements = load(&document(source)).expect("round-trips");
+        let expected = ast::parse(scan(source, false).expect("source lexes")).expect("source parses");
+        assert_eq!(ast::print(&statements, false), ast::print(&expected, false));
+    }
+
+    #[test]
+    fn number_literals_round_trip_exactly() {
+        let source = "print 1.7976931348623157e308\nprint 5e-324\nprint 0x1F_FFFF_FFFF_FFFF\nprint 0.1";
+        let statements = ast::parse(scan(source, false).expect("source lexes")).expect("source parses");
+        let loaded = ast_from_json(&ast_to_json(&statements)).expect("round-trips");
+        let numbers: Vec<f64> = loaded.iter().map(|statement| match statement {
+            Statement::Print(Expression::Literal(Token { literal: Literal::Number(n), .. })) => *n,
+            other => panic!("expected a printed number, got {other:?}"),
+        }).collect();
+        assert_eq!(numbers, [f64::MAX, 5e-324, 9_007_199_254_740_991.0, 0.1]);
+    }
+
+    #[test]
+    fn rejects_other_schema_versions() {
+        let mut document = document("print 1");
+        document["version"] = json!(SCHEMA_VERSION + 1);
+        assert!(load(&document).unwrap_err().starts_with("unsupported AST schema version"));
+    }
+
+    #[test]
+    fn rejects_malformed_interpolations() {
+        let original = document("print \"a${1}b\" + 1");
+        let interpolation = "/statements/0/fields/fields/0/fields";
+        let parts = original.pointer(interpolation).and_then(Value::as_array).expect("interpolation parts").clone();
+        assert_eq!(parts.len(), 3);
+
+        for parts in [json!([]), json!([parts[1]]), json!([parts[0], parts[2]])] {
+            let mut document = original.clone();
+            *document.pointer_mut(interpolation).unwrap() = parts;
+            assert!(load(&document).unwrap_err().starts_with("interpolation parts must alternate"));
+        }
+    }
+
+    #[test]
+    fn rejects_declarations_as_branch_bodies() {
+        for source in ["if (true) { let x = 1 }", "if (true) {} else { fn f() {} }", "while (false) { class C {} }"] {
+            let mut document = document(source);
+            let branch = if source.contains("else") { "/statements/0/fields/2" } else { "/statements/0/fields/1" };
+            let declaration = document.pointer(&(branch.to_string() + "/fields/0")).unwrap().clone();
+            *document.pointer_mut(branch).unwrap() = declaration;
+            let error = load(&document).unwrap_err();
+            assert!(error.ends_with("must be at the top level or directly in a block"), "{source}: {error}");
+        }
+    }
+}
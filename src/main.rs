@@ -1,7 +1,11 @@
 #![warn(clippy::all, clippy::pedantic)]
+use std::cell::RefCell;
 use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{IsTerminal, Read, Write};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::thread;
 
 mod error_handling;
 mod scanner;
@@ -9,39 +13,211 @@ mod token;
 mod ast;
 mod interpreter;
 mod environment;
+mod json;
+mod resolver;
+mod visit;
 
-fn run(source: &str, env: &mut environment::Environment) {
-    let tokens = scanner::scan(source);
-    let stmts = ast::parse(tokens);
+/// The interpreter recurses on the native stack, so it runs on a thread with
+/// room for the deepest call nesting it allows even in an unoptimised build.
+const STACK_SIZE: usize = 64 * 1024 * 1024;
 
-    //ast::print(&stmts);
-    //println!();
-    interpreter::execute(&stmts, env);
+const USAGE: &str = "usage: intrprtr-rs [--dump-tokens | --dump-ast | --emit=tokens-json | --emit=ast-json] [--trivia] [--from=ast-json] [script]";
+
+enum Mode {
+    Run,
+    DumpTokens,
+    DumpAst,
+    EmitTokensJson,
+    EmitAstJson,
+}
+
+fn scan(source: &str, trivia: bool) -> Option<Vec<token::Token>> {
+    match scanner::scan(source, trivia) {
+        Ok(tokens) => Some(tokens),
+        Err(errors) => {
+            for err in errors {
+                error_handling::error(err.span, &err.message);
+            }
+            None
+        },
+    }
+}
+
+fn parse(source: &str, from_json: bool) -> Option<Vec<environment::Statement>> {
+    if from_json {
+        return match json::ast_from_json(source) {
+            Ok(stmts) => Some(stmts),
+            Err(err) => {
+                eprintln!("invalid AST JSON: {err}");
+                None
+            },
+        };
+    }
+
+    match ast::parse(scan(source, false)?) {
+        Ok(stmts) => Some(stmts),
+        Err(errors) => {
+            for err in errors {
+                error_handling::error(err.span, &err.message);
+            }
+            None
+        },
+    }
 }
 
-fn run_file(args: Vec<String>) {
-    let contents = fs::read_to_string(&args[1]).expect("Something went wrong reading the file");
-    for line in contents.split_terminator('\n') {
-        run(&line, &mut environment::Environment::new());
+/// Why `run` stopped before the end of the program.
+enum Halt {
+    /// Lexing, parsing or resolving failed, so nothing was executed.
+    Refused,
+    /// A runtime error stopped execution part way through.
+    Failed,
+    Exit(u8),
+}
+
+fn run(source: &str, from_json: bool, env: &Rc<RefCell<environment::Environment>>, resolver: &mut resolver::Resolver) -> Result<(), Halt> {
+    let mut stmts = parse(source, from_json).ok_or(Halt::Refused)?;
+    match resolver.resolve(&mut stmts) {
+        Ok(warnings) => {
+            for warning in warnings {
+                error_handling::warning(warning.span, &warning.message);
+            }
+        },
+        Err(errors) => {
+            for err in errors {
+                error_handling::error(err.span, &err.message);
+            }
+            return Err(Halt::Refused);
+        },
+    }
+
+    match interpreter::execute(&stmts, env) {
+        Ok(()) | Err(interpreter::Unwind::Return(_)) => Ok(()),
+        Err(interpreter::Unwind::Exit(code)) => Err(Halt::Exit(code)),
+        Err(interpreter::Unwind::Error) => Err(Halt::Failed),
     }
 }
 
-fn run_prompt() {
+fn globals() -> Rc<RefCell<environment::Environment>> {
     let mut env = environment::Environment::new();
+    interpreter::define_natives(&mut env);
+    Rc::new(RefCell::new(env))
+}
+
+fn read_source(path: Option<&str>) -> String {
+    if let Some(path) = path {
+        fs::read_to_string(path).expect("Something went wrong reading the file")
+    } else {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source).expect("Something went wrong reading stdin");
+        source
+    }
+}
+
+fn dump_tokens(source: &str, trivia: bool) -> u8 {
+    let Some(tokens) = scan(source, trivia) else { return 65 };
+    for token in tokens {
+        let token::Span { line, column, .. } = token.span;
+        match token.literal {
+            token::Literal::None => println!("{line}:{column} {:?} {:?}", token.token_type, token.lexeme),
+            literal => println!("{line}:{column} {:?} {:?} {literal:?}", token.token_type, token.lexeme),
+        }
+    }
+    0
+}
+
+fn emit_tokens_json(source: &str, trivia: bool) -> u8 {
+    let Some(tokens) = scan(source, trivia) else { return 65 };
+    println!("{}", json::tokens_to_json(&tokens));
+    0
+}
+
+fn dump_ast(source: &str, from_json: bool, emit_json: bool) -> u8 {
+    match parse(source, from_json) {
+        Some(stmts) if emit_json => {
+            println!("{}", json::ast_to_json(&stmts));
+            0
+        },
+        Some(stmts) => {
+            print!("{}", ast::print(&stmts, std::io::stdout().is_terminal()));
+            0
+        },
+        None => 65,
+    }
+}
+
+fn run_source(source: &str, from_json: bool) -> u8 {
+    let env = globals();
+    let mut resolver = resolver::Resolver::new(env.borrow().names());
+    match run(source, from_json, &env, &mut resolver) {
+        Ok(()) => 0,
+        Err(Halt::Refused) => 65,
+        Err(Halt::Failed) => 70,
+        Err(Halt::Exit(code)) => code,
+    }
+}
+
+fn run_prompt() -> u8 {
+    let env = globals();
+    let mut resolver = resolver::Resolver::new(env.borrow().names()).interactive(true);
     loop {
         let mut input = String::new();
         print!("\x1b[1;37m>>> \x1b[0m");
         std::io::stdout().flush().unwrap();
-        std::io::stdin().read_line(&mut input).unwrap();
-        run(&input, &mut env);
+        if std::io::stdin().read_line(&mut input).unwrap() == 0 {
+            println!();
+            return 0;
+        }
+        if let Err(Halt::Exit(code)) = run(&input, false, &env, &mut resolver) {
+            return code;
+        }
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        run_file(args);
-    } else {
-        run_prompt();
+fn start() -> u8 {
+    let mut mode = Mode::Run;
+    let mut from_json = false;
+    let mut trivia = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--dump-tokens" => mode = Mode::DumpTokens,
+            "--dump-ast" => mode = Mode::DumpAst,
+            "--emit=tokens-json" => mode = Mode::EmitTokensJson,
+            "--emit=ast-json" => mode = Mode::EmitAstJson,
+            "--from=ast-json" => from_json = true,
+            "--trivia" => trivia = true,
+            _ if arg.starts_with("--") || path.is_some() => {
+                eprintln!("{USAGE}");
+                return 64;
+            },
+            _ => path = Some(arg),
+        }
     }
+
+    let code = match (mode, path.as_deref()) {
+        (Mode::DumpTokens | Mode::EmitTokensJson, _) if from_json => {
+            eprintln!("{USAGE}");
+            64
+        },
+        (Mode::DumpAst | Mode::EmitAstJson | Mode::Run, _) if trivia => {
+            eprintln!("{USAGE}");
+            64
+        },
+        (Mode::DumpTokens, path) => dump_tokens(&read_source(path), trivia),
+        (Mode::EmitTokensJson, path) => emit_tokens_json(&read_source(path), trivia),
+        (Mode::DumpAst, path) => dump_ast(&read_source(path), from_json, false),
+        (Mode::EmitAstJson, path) => dump_ast(&read_source(path), from_json, true),
+        (Mode::Run, path) if from_json => run_source(&read_source(path), true),
+        (Mode::Run, Some(path)) => run_source(&read_source(Some(path)), false),
+        (Mode::Run, None) => run_prompt(),
+    };
+
+    std::io::stdout().flush().unwrap();
+    code
+}
+
+fn main() -> ExitCode {
+    let interpreter = thread::Builder::new().stack_size(STACK_SIZE).spawn(start).expect("failed to start the interpreter thread");
+    // A panic has already been reported by the thread; exit as an unwinding main would.
+    ExitCode::from(interpreter.join().unwrap_or(101))
 }
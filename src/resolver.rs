@@ -0,0 +1,299 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::token::{Span, Token};
+use crate::environment::{Expression, FunctionDeclaration, Slot, Statement};
+use crate::visit::{VisitorMut, walk_function_mut};
+
+#[derive(Debug, Clone)]
+pub struct ResolveError {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    None,
+    Function,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+struct Local {
+    span: Span,
+    slot: usize,
+    defined: bool,
+    used: bool,
+}
+
+/// Static pass run between parsing and execution. Records the slot each local
+/// reference lives in and rejects programs that could only fail at
+/// runtime. Globals persist across calls so the REPL can resolve line by line.
+pub struct Resolver {
+    globals: HashSet<String>,
+    scopes: Vec<HashMap<String, Local>>,
+    function: FunctionKind,
+    class: ClassKind,
+    /// Globals read by top-level code before any declaration of them.
+    early: Vec<Token>,
+    /// Globals read inside function bodies, checked once the whole program is known.
+    deferred: Vec<Token>,
+    /// Later input may still define them, so deferred globals only warn.
+    interactive: bool,
+    errors: Vec<ResolveError>,
+    /// Non-fatal findings, only worth reporting if the program is going to run.
+    warnings: Vec<ResolveError>,
+}
+
+impl Resolver {
+    pub fn new<'a>(globals: impl Iterator<Item = &'a String>) -> Self {
+        Resolver {
+            globals: globals.cloned().collect(),
+            scopes: Vec::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            early: Vec::new(),
+            deferred: Vec::new(),
+            interactive: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Returns the warnings when the program may run, or the errors when it
+    /// can't, sorted by position either way.
+    pub fn resolve(&mut self, statements: &mut [Statement]) -> Result<Vec<ResolveError>, Vec<ResolveError>> {
+        let known = self.globals.clone();
+        for statement in statements {
+            self.visit_statement_mut(statement);
+        }
+
+        for name in std::mem::take(&mut self.early) {
+            if self.globals.contains(&name.lexeme) {
+                self.error(name.span, &("'".to_string() + &name.lexeme + "' is used before its definition"));
+            } else {
+                self.error(name.span, &("undefined variable '".to_string() + &name.lexeme + "'"));
+            }
+        }
+        for name in std::mem::take(&mut self.deferred) {
+            if self.globals.contains(&name.lexeme) {
+                continue;
+            }
+            if self.interactive {
+                self.warning(name.span, &("'".to_string() + &name.lexeme + "' is not defined yet"));
+            } else {
+                self.error(name.span, &("undefined variable '".to_string() + &name.lexeme + "'"));
+            }
+        }
+
+        let mut warnings = std::mem::take(&mut self.warnings);
+        if self.errors.is_empty() {
+            warnings.sort_by_key(|warning| (warning.span.line, warning.span.column));
+            return Ok(warnings);
+        }
+        self.globals = known;
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|error| (error.span.line, error.span.column));
+        Err(errors)
+    }
+
+    fn error(&mut self, span: Span, message: &str) {
+        self.errors.push(ResolveError { span, message: message.to_string() });
+    }
+
+    fn warning(&mut self, span: Span, message: &str) {
+        self.warnings.push(ResolveError { span, message: message.to_string() });
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else { return };
+        for (name, local) in scope.iter().filter(|(name, local)| !local.used && !name.starts_with('_')) {
+            self.warning(local.span, &("unused local variable '".to_string() + name + "'"));
+        }
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else { return };
+        if scope.contains_key(&name.lexeme) {
+            self.error(name.span, &("'".to_string() + &name.lexeme + "' is already declared in this scope"));
+            return;
+        }
+        let slot = scope.len();
+        scope.insert(name.lexeme.clone(), Local { span: name.span, slot, defined: false, used: false });
+    }
+
+    fn define(&mut self, name: &Token) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                if let Some(local) = scope.get_mut(&name.lexeme) {
+                    local.defined = true;
+                }
+            },
+            None => { self.globals.insert(name.lexeme.clone()); },
+        }
+    }
+
+    /// Defines a name the interpreter binds implicitly, such as a parameter or `this`.
+    fn define_implicit(&mut self, name: &str, span: Span) {
+        let Some(scope) = self.scopes.last_mut() else { return };
+        if let Some(local) = scope.get_mut(name) {
+            local.defined = true;
+            local.used = true;
+            return;
+        }
+        let slot = scope.len();
+        scope.insert(name.to_string(), Local { span, slot, defined: true, used: true });
+    }
+
+    fn resolve_local(&mut self, name: &Token, read: bool) -> Option<Slot> {
+        let found = self.scopes.iter_mut().rev().enumerate().find_map(|(depth, scope)| {
+            let local = scope.get_mut(&name.lexeme)?;
+            local.used |= read;
+            Some((Slot { depth, index: local.slot }, local.defined))
+        });
+        if let Some((slot, defined)) = found {
+            if read && !defined {
+                self.error(name.span, &("can't read local variable '".to_string() + &name.lexeme + "' in its own initializer"));
+            }
+            return Some(slot);
+        }
+
+        if !self.globals.contains(&name.lexeme) {
+            if self.function == FunctionKind::None {
+                self.early.push(name.clone());
+            } else {
+                self.deferred.push(name.clone());
+            }
+        }
+        None
+    }
+
+    fn resolve_function(&mut self, declaration: &mut FunctionDeclaration, kind: FunctionKind) {
+        let enclosing = self.function;
+        self.function = kind;
+
+        self.begin_scope();
+        for param in &declaration.params {
+            self.declare(param);
+            self.define_implicit(&param.lexeme, param.span);
+        }
+        walk_function_mut(self, declaration);
+        self.end_scope();
+
+        self.function = enclosing;
+    }
+}
+
+impl VisitorMut for Resolver {
+    fn visit_block_mut(&mut self, statements: &mut Vec<Statement>) {
+        self.begin_scope();
+        for statement in statements {
+            self.visit_statement_mut(statement);
+        }
+        self.end_scope();
+    }
+
+    fn visit_var_mut(&mut self, name: &mut Token, initializer: &mut Expression) {
+        self.declare(name);
+        self.visit_expression_mut(initializer);
+        self.define(name);
+    }
+
+    fn visit_function_statement_mut(&mut self, declaration: &mut FunctionDeclaration) {
+        self.declare(&declaration.name);
+        self.define(&declaration.name);
+        self.resolve_function(declaration, FunctionKind::Function);
+    }
+
+    fn visit_lambda_mut(&mut self, declaration: &mut FunctionDeclaration) {
+        self.resolve_function(declaration, FunctionKind::Function);
+    }
+
+    fn visit_return_mut(&mut self, keyword: &mut Token, value: Option<&mut Expression>) {
+        if self.function == FunctionKind::None {
+            self.error(keyword.span, "can't return from top-level code");
+        }
+        if let Some(value) = value {
+            self.visit_expression_mut(value);
+        }
+    }
+
+    fn visit_class_mut(&mut self, name: &mut Token, superclass: Option<&mut Expression>, methods: &mut Vec<Rc<FunctionDeclaration>>) {
+        let enclosing = self.class;
+        self.class = ClassKind::Class;
+        self.declare(name);
+        self.define(name);
+
+        if let Some(superclass) = superclass {
+            if let Expression::Variable(parent, _) = superclass {
+                if parent.lexeme == name.lexeme {
+                    self.error(parent.span, "a class can't inherit from itself");
+                }
+            }
+            self.class = ClassKind::Subclass;
+            self.visit_expression_mut(superclass);
+            self.begin_scope();
+            self.define_implicit("super", name.span);
+        }
+
+        self.begin_scope();
+        self.define_implicit("this", name.span);
+        for method in methods {
+            self.resolve_function(Rc::make_mut(method), FunctionKind::Function);
+        }
+        self.end_scope();
+
+        if self.class == ClassKind::Subclass {
+            self.end_scope();
+        }
+        self.class = enclosing;
+    }
+
+    fn visit_variable_mut(&mut self, name: &mut Token, slot: &mut Option<Slot>) {
+        *slot = self.resolve_local(name, true);
+    }
+
+    fn visit_assign_mut(&mut self, name: &mut Token, value: &mut Expression, slot: &mut Option<Slot>) {
+        self.visit_expression_mut(value);
+        *slot = self.resolve_local(name, false);
+    }
+
+    fn visit_compound_assign_mut(&mut self, name: &mut Token, _op: &mut Token, value: &mut Expression, slot: &mut Option<Slot>) {
+        self.visit_expression_mut(value);
+        *slot = self.resolve_local(name, true);
+    }
+
+    fn visit_update_mut(&mut self, name: &mut Token, _op: &mut Token, _prefix: &mut bool, slot: &mut Option<Slot>) {
+        *slot = self.resolve_local(name, true);
+    }
+
+    fn visit_this_mut(&mut self, keyword: &mut Token, slot: &mut Option<Slot>) {
+        if self.class == ClassKind::None {
+            self.error(keyword.span, "can't use 'this' outside of a class");
+            return;
+        }
+        *slot = self.resolve_local(keyword, true);
+    }
+
+    fn visit_super_mut(&mut self, keyword: &mut Token, _method: &mut Token, slot: &mut Option<Slot>) {
+        match self.class {
+            ClassKind::None => self.error(keyword.span, "can't use 'super' outside of a class"),
+            ClassKind::Class => self.error(keyword.span, "can't use 'super' in a class with no superclass"),
+            ClassKind::Subclass => *slot = self.resolve_local(keyword, true),
+        }
+    }
+}
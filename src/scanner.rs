@@ -1,145 +1,855 @@
-use crate::token::*;
-use crate::error_handling::error;
+use crate::token::{Literal, Span, Token, TokenType};
+use unicode_ident::{is_xid_continue, is_xid_start};
 
-pub fn scan(source: &str) -> Vec<Token> {
-    let mut tokens: Vec<Token> = Vec::new();
-    let mut line = 0;
-    let mut word_index = 0;
+struct SourceMap {
+    offsets: Vec<usize>,
+    line_starts: Vec<usize>,
+}
 
-    let mut i = 0;
-    let source = source.chars().collect::<Vec<char>>();
-    while i < source.len() {
-        let mut literal = Vec::new();
-        literal.push(source[i]);
+impl SourceMap {
+    fn new(source: &str) -> Self {
+        let mut offsets = Vec::new();
+        let mut line_starts = vec![0];
+        for (i, (offset, c)) in source.char_indices().enumerate() {
+            offsets.push(offset);
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        offsets.push(source.len());
+
+        SourceMap { offsets, line_starts }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        let (line, column) = self.position(start);
+        let (end_line, end_column) = self.position(end);
+        Span {
+            start: self.offsets[start],
+            end: self.offsets[end],
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+
+    /// The 1-based line and character column of the character at index `i`.
+    fn position(&self, i: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|s| *s <= i);
+        (line, i - self.line_starts[line - 1] + 1)
+    }
+
+    fn text(source: &[char], start: usize, end: usize) -> String {
+        source[start..end].iter().collect::<String>()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ScanError {
+    fn new(span: Span, message: &str) -> ScanError {
+        ScanError { span, message: message.to_string() }
+    }
+}
+
+pub fn scan(source: &str, trivia: bool) -> Result<Vec<Token>, Vec<ScanError>> {
+    let mut lexer = Lexer::new(source).with_trivia(trivia);
+    let tokens = lexer.by_ref().collect();
+    if lexer.errors().is_empty() {
+        Ok(tokens)
+    } else {
+        Err(lexer.errors)
+    }
+}
+
+/// Yields the tokens of `source` one at a time, ending with a single `EOF`.
+/// Tokens are produced on demand, but `new` indexes the whole input first,
+/// so this is not a streaming reader over partial input.
+///
+/// With trivia enabled every byte of the input belongs to exactly one token,
+/// so concatenating the lexemes reproduces the source. Without it, whitespace,
+/// comments and unrecognised characters are skipped, and a newline becomes a
+/// `SEMICOLON` only when the previous token can end a statement and the
+/// innermost open delimiter is a brace.
+///
+/// Malformed input never stops the stream; diagnostics are collected on the
+/// side and read back with `errors`.
+///
+/// The crate is a binary only, so this is internal to the interpreter; outside
+/// tools get the same tokens from `--emit=tokens-json [--trivia]`.
+pub struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<char>,
+    map: SourceMap,
+    current: usize,
+    trivia: bool,
+    finished: bool,
+    delimiters: Vec<TokenType>,
+    /// Where each `${` still open on `delimiters` starts.
+    interpolations: Vec<usize>,
+    previous: Option<TokenType>,
+    errors: Vec<ScanError>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Lexer {
+            source,
+            chars: source.chars().collect(),
+            map: SourceMap::new(source),
+            current: 0,
+            trivia: false,
+            finished: false,
+            delimiters: Vec::new(),
+            interpolations: Vec::new(),
+            previous: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_trivia(mut self, trivia: bool) -> Self {
+        self.trivia = trivia;
+        self
+    }
+
+    pub fn errors(&self) -> &[ScanError] {
+        &self.errors
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn lex(&mut self) -> (TokenType, Literal) {
+        let source = &self.chars;
+        let map = &self.map;
+        let start = self.current;
+        let mut i = self.current;
 
-        match source[i] {
+        let token = match source[i] {
             '/' => {
                 if i + 1 < source.len() && source[i + 1] == '/' {
                     while i + 1 < source.len() && source[i + 1] != '\n' {
                         i += 1;
                     }
+                    (TokenType::LINE_COMMENT, Literal::None)
+                } else if i + 1 < source.len() && source[i + 1] == '*' {
+                    block_comment(source, &mut i, map, &mut self.errors);
+                    (TokenType::BLOCK_COMMENT, Literal::None)
+                } else if i + 1 < source.len() && source[i + 1] == '=' {
+                    i += 1;
+                    (TokenType::SLASH_EQUAL, Literal::None)
+                } else {
+                    (TokenType::SLASH, Literal::None)
                 }
-
-                if i + 1 < source.len() && source[i + 1] == '*' {
-                    while i + 2 < source.len() && source[i + 1] != '*' && source[i + 2] != '/' {
-                        i += 1;
-                    }
+            },
+            '{' => {
+                self.delimiters.push(TokenType::LEFT_BRACE);
+                (TokenType::LEFT_BRACE, Literal::None)
+            },
+            '}' if self.delimiters.last() == Some(&TokenType::INTERPOLATION) => {
+                self.delimiters.pop();
+                self.interpolations.pop();
+                self.string(&mut i)
+            },
+            '}' => {
+                self.close(TokenType::LEFT_BRACE);
+                (TokenType::RIGHT_BRACE, Literal::None)
+            },
+            '(' => {
+                self.delimiters.push(TokenType::LEFT_PAREN);
+                (TokenType::LEFT_PAREN, Literal::None)
+            },
+            ')' => {
+                self.close(TokenType::LEFT_PAREN);
+                (TokenType::RIGHT_PAREN, Literal::None)
+            },
+            '[' => {
+                self.delimiters.push(TokenType::LEFT_BRACKET);
+                (TokenType::LEFT_BRACKET, Literal::None)
+            },
+            ']' => {
+                self.close(TokenType::LEFT_BRACKET);
+                (TokenType::RIGHT_BRACKET, Literal::None)
+            },
+            ',' => (TokenType::COMMA, Literal::None),
+            '.' => (TokenType::DOT, Literal::None),
+            '-' => {
+                if i + 1 < source.len() && source[i + 1] == '=' {
+                    i += 1;
+                    (TokenType::MINUS_EQUAL, Literal::None)
+                } else if i + 1 < source.len() && source[i + 1] == '-' {
+                    i += 1;
+                    (TokenType::MINUS_MINUS, Literal::None)
+                } else {
+                    (TokenType::MINUS, Literal::None)
                 }
-
-                tokens.push(Token::new(TokenType::SLASH, "/".to_string(), literal, line, word_index))
-            },
-            '{' => tokens.push(Token::new(TokenType::LEFT_BRACE, "{".to_string(), literal, line, word_index)),
-            '}' => tokens.push(Token::new(TokenType::RIGHT_BRACE, "}".to_string(), literal, line, word_index)),
-            '(' => tokens.push(Token::new(TokenType::LEFT_PAREN, "(".to_string(), literal, line, word_index)),
-            ')' => tokens.push(Token::new(TokenType::RIGHT_PAREN, ")".to_string(), literal, line, word_index)),
-            ',' => tokens.push(Token::new(TokenType::COMMA, ",".to_string(), literal, line, word_index)),
-            '.' => tokens.push(Token::new(TokenType::DOT, ".".to_string(), literal, line, word_index)),
-            '-' => tokens.push(Token::new(TokenType::MINUS, "-".to_string(), literal, line, word_index)),
-            '+' => tokens.push(Token::new(TokenType::PLUS, "+".to_string(), literal, line, word_index)),
-            ';' => tokens.push(Token::new(TokenType::SEMICOLON, ";".to_string(), literal, line, word_index)),
-            '*' => tokens.push(Token::new(TokenType::STAR, "*".to_string(), literal, line, word_index)),
+            },
+            '+' => {
+                if i + 1 < source.len() && source[i + 1] == '=' {
+                    i += 1;
+                    (TokenType::PLUS_EQUAL, Literal::None)
+                } else if i + 1 < source.len() && source[i + 1] == '+' {
+                    i += 1;
+                    (TokenType::PLUS_PLUS, Literal::None)
+                } else {
+                    (TokenType::PLUS, Literal::None)
+                }
+            },
+            ';' => (TokenType::SEMICOLON, Literal::None),
+            '*' => {
+                if i + 1 < source.len() && source[i + 1] == '=' {
+                    i += 1;
+                    (TokenType::STAR_EQUAL, Literal::None)
+                } else {
+                    (TokenType::STAR, Literal::None)
+                }
+            },
+            '%' => {
+                if i + 1 < source.len() && source[i + 1] == '=' {
+                    i += 1;
+                    (TokenType::PERCENT_EQUAL, Literal::None)
+                } else {
+                    (TokenType::PERCENT, Literal::None)
+                }
+            },
             '!' => {
                 if i + 1 < source.len() && source[i + 1] == '=' {
-                    tokens.push(Token::new(TokenType::BANG_EQUAL, "!=".to_string(), literal, line, word_index));
                     i += 1;
+                    (TokenType::BANG_EQUAL, Literal::None)
                 } else {
-                    tokens.push(Token::new(TokenType::BANG, "!".to_string(), literal, line, word_index));
+                    (TokenType::BANG, Literal::None)
                 }
             },
             '=' => {
                 if i + 1 < source.len() && source[i + 1] == '=' {
-                    tokens.push(Token::new(TokenType::EQUAL_EQUAL, "==".to_string(), literal, line, word_index));
                     i += 1;
+                    (TokenType::EQUAL_EQUAL, Literal::None)
+                } else if i + 1 < source.len() && source[i + 1] == '>' {
+                    i += 1;
+                    (TokenType::ARROW, Literal::None)
                 } else {
-                    tokens.push(Token::new(TokenType::EQUAL, "=".to_string(), literal, line, word_index));
+                    (TokenType::EQUAL, Literal::None)
                 }
             },
             '>' => {
                 if i + 1 < source.len() && source[i + 1] == '=' {
-                    tokens.push(Token::new(TokenType::GREATER_EQUAL, ">=".to_string(), literal, line, word_index));
                     i += 1;
+                    (TokenType::GREATER_EQUAL, Literal::None)
                 } else {
-                    tokens.push(Token::new(TokenType::GREATER, ">".to_string(), literal, line, word_index));
+                    (TokenType::GREATER, Literal::None)
                 }
             },
             '<' => {
                 if i + 1 < source.len() && source[i + 1] == '=' {
-                    tokens.push(Token::new(TokenType::LESS_EQUAL, "<=".to_string(), literal, line, word_index));
                     i += 1;
+                    (TokenType::LESS_EQUAL, Literal::None)
                 } else {
-                    tokens.push(Token::new(TokenType::LESS, "<".to_string(), literal, line, word_index));
+                    (TokenType::LESS, Literal::None)
                 }
             },
-            ' ' => {
-                word_index += 1;
-            },
-            '\t' => {
-                word_index += 1;
+            ' ' | '\t' | '\r' => {
+                while i + 1 < source.len() && matches!(source[i + 1], ' ' | '\t' | '\r') {
+                    i += 1;
+                }
+                (TokenType::WHITESPACE, Literal::None)
             },
-            '\n' => {
-                tokens.push(Token::new(TokenType::SEMICOLON, " ".to_string(), literal, line, word_index));
-                line += 1;
-                word_index = 0;
+            '\n' => (TokenType::NEWLINE, Literal::None),
+            '"' => self.string(&mut i),
+            'r' if matches!(source.get(i + 1), Some('"' | '#')) => {
+                (TokenType::STRING, Literal::String(raw_string(source, &mut i, map, &mut self.errors)))
             },
-            '\r' => {
-                line += 1;
-                word_index = 0;
+            '0'..='9' => match number(source, &mut i, map, &mut self.errors) {
+                Some(n) => (TokenType::NUMBER, Literal::Number(n)),
+                None => (TokenType::ERROR, Literal::None),
             },
-            '"' => {
-                literal.pop();
-                while i + 1 < source.len() && source[i + 1] != '"' {
-                    literal.push(source[i + 1]);
+            c if c == '_' || is_xid_start(c) => {
+                while i + 1 < source.len() && is_xid_continue(source[i + 1]) {
                     i += 1;
                 }
 
-                if i + 1 == source.len() {
-                    error(line, word_index, "Unterminated string");
-                }
+                let token_type = match SourceMap::text(source, start, i + 1).as_ref() {
+                    "and" => TokenType::AND,
+                    "class" => TokenType::CLASS,
+                    "else" => TokenType::ELSE,
+                    "false" => TokenType::FALSE,
+                    "for" => TokenType::FOR,
+                    "fn" => TokenType::FUN,
+                    "if" => TokenType::IF,
+                    "null" => TokenType::NIL,
+                    "or" => TokenType::OR,
+                    "print" => TokenType::PRINT,
+                    "return" => TokenType::RETURN,
+                    "super" => TokenType::SUPER,
+                    "this" => TokenType::THIS,
+                    "true" => TokenType::TRUE,
+                    "let" => TokenType::VAR,
+                    "while" => TokenType::WHILE,
+                    _ => TokenType::IDENTIFIER,
+                };
+                (token_type, Literal::None)
+            },
+            _ => {
+                self.errors.push(ScanError::new(map.span(i, i + 1), &("unexpected token '".to_owned() + &source[i].to_string() + "'")));
+                (TokenType::ERROR, Literal::None)
+            },
+        };
+
+        self.current = i + 1;
+        token
+    }
 
-                i += 1;
-                tokens.push(Token::new(TokenType::STRING, literal.iter().collect::<String>(), literal, line, word_index));
+    fn string(&mut self, i: &mut usize) -> (TokenType, Literal) {
+        let start = *i;
+        let (value, end) = string(&self.chars, i, &self.map, &mut self.errors);
+        match end {
+            StringEnd::Quote => (TokenType::STRING, Literal::String(value)),
+            StringEnd::Interpolation => {
+                self.delimiters.push(TokenType::INTERPOLATION);
+                self.interpolations.push(*i - 1);
+                (TokenType::INTERPOLATION, Literal::String(value))
             },
-            '0'..='9' => {
-                while i + 1 < source.len() && source[i + 1] >= '0' && source[i + 1] <= '9' {
-                    literal.push(source[i + 1]);
-                    i += 1;
+            StringEnd::Eof => {
+                // A quote inside `${...}` that runs to the end of the input almost
+                // always means the `}` before it is missing.
+                match self.interpolations.last() {
+                    Some(&open) if self.delimiters.last() == Some(&TokenType::INTERPOLATION) => {
+                        self.errors.push(ScanError::new(self.map.span(open, open + 2), "unterminated interpolation: expected '}' before the closing quote"));
+                    },
+                    _ => self.errors.push(ScanError::new(self.map.span(start, *i + 1), "unterminated string")),
                 }
-                tokens.push(Token::new(TokenType::NUMBER, literal.iter().collect::<String>(), literal, line, word_index));
+                (TokenType::STRING, Literal::String(value))
             },
-            'a'..='z' | 'A'..='Z' | '_' => {
-                while i + 1 < source.len() && (source[i + 1] >= 'a' && source[i + 1] <= 'z') || (source[i + 1] >= 'A' && source[i + 1] <= 'Z') || source[i + 1] == '_' {
-                    literal.push(source[i + 1]);
-                    i += 1;
+        }
+    }
+
+    fn close(&mut self, open: TokenType) {
+        if self.delimiters.last() == Some(&open) {
+            self.delimiters.pop();
+        }
+    }
+
+    fn terminates_statement(&self) -> bool {
+        self.previous.is_some_and(TokenType::ends_statement)
+            && self.delimiters.last().is_none_or(|open| *open == TokenType::LEFT_BRACE)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if self.current >= self.chars.len() {
+                if self.finished {
+                    return None;
                 }
+                self.finished = true;
+                let span = self.map.span(self.chars.len(), self.chars.len());
+                return Some(Token::new(TokenType::EOF, String::new(), Literal::None, span));
+            }
 
-                match literal.iter().collect::<String>().as_ref() {
-                    "and" => tokens.push(Token::new(TokenType::AND, "and".to_string(), literal, line, word_index)),
-                    "class" => tokens.push(Token::new(TokenType::CLASS, "class".to_string(), literal, line, word_index)),
-                    "else" => tokens.push(Token::new(TokenType::ELSE, "else".to_string(), literal, line, word_index)),
-                    "false" => tokens.push(Token::new(TokenType::FALSE, "false".to_string(), literal, line, word_index)),
-                    "for" => tokens.push(Token::new(TokenType::FOR, "for".to_string(), literal, line, word_index)),
-                    "fn" => tokens.push(Token::new(TokenType::FUN, "fun".to_string(), literal, line, word_index)),
-                    "if" => tokens.push(Token::new(TokenType::IF, "if".to_string(), literal, line, word_index)),
-                    "null" => tokens.push(Token::new(TokenType::NIL, "null".to_string(), literal, line, word_index)),
-                    "or" => tokens.push(Token::new(TokenType::OR, "or".to_string(), literal, line, word_index)),
-                    "print" => tokens.push(Token::new(TokenType::PRINT, "print".to_string(), literal, line, word_index)),
-                    "return" => tokens.push(Token::new(TokenType::RETURN, "return".to_string(), literal, line, word_index)),
-                    "super" => tokens.push(Token::new(TokenType::SUPER, "super".to_string(), literal, line, word_index)),
-                    "this" => tokens.push(Token::new(TokenType::THIS, "this".to_string(), literal, line, word_index)),
-                    "true" => tokens.push(Token::new(TokenType::TRUE, "true".to_string(), literal, line, word_index)),
-                    "let" => tokens.push(Token::new(TokenType::VAR, "let".to_string(), literal, line, word_index)),
-                    "while" => tokens.push(Token::new(TokenType::WHILE, "while".to_string(), literal, line, word_index)),
-                    "exit" => std::process::exit(0),
-                    "exit()" => std::process::exit(0),
-                    _ => tokens.push(Token::new(TokenType::IDENTIFIER, literal.iter().collect::<String>(), literal, line, word_index)),
+            let start = self.current;
+            let (mut token_type, literal) = self.lex();
+            if !self.trivia {
+                if token_type == TokenType::NEWLINE && self.terminates_statement() {
+                    token_type = TokenType::SEMICOLON;
+                } else if token_type.is_trivia() {
+                    continue;
                 }
+                self.previous = Some(token_type);
+            }
+
+            let span = self.map.span(start, self.current);
+            let lexeme = self.source[span.start..span.end].to_string();
+            return Some(Token::new(token_type, lexeme, literal, span));
+        }
+    }
+}
+
+/// Lexes a number literal, or returns `None` after reporting why it is malformed.
+fn number(source: &[char], i: &mut usize, map: &SourceMap, errors: &mut Vec<ScanError>) -> Option<f64> {
+    let start = *i;
+    let reported = errors.len();
+    let radix = match source.get(*i + 1) {
+        Some('x' | 'X') if source[*i] == '0' => 16,
+        Some('o' | 'O') if source[*i] == '0' => 8,
+        Some('b' | 'B') if source[*i] == '0' => 2,
+        _ => 10,
+    };
+
+    if radix != 10 {
+        *i += 1;
+        digits(source, start, i, char::is_ascii_alphanumeric, map, errors);
+
+        let text = SourceMap::text(source, start, *i + 1);
+        let body = text[2..].chars().filter(|c| *c != '_').collect::<String>();
+        if body.is_empty() {
+            errors.push(ScanError::new(map.span(start, *i + 1), &("expected digits after the prefix of '".to_owned() + &text + "'")));
+            return None;
+        }
+        if let Some(c) = body.chars().find(|c| !c.is_digit(radix)) {
+            errors.push(ScanError::new(map.span(start, *i + 1), &("invalid digit '".to_owned() + &c.to_string() + "' in base " + &radix.to_string() + " literal '" + &text + "'")));
+            return None;
+        }
+        // Numbers are f64, so past 2^53 only values whose set bits fit in the
+        // mantissa survive the conversion unchanged.
+        #[allow(clippy::cast_precision_loss)]
+        return match u64::from_str_radix(&body, radix) {
+            Ok(n) if n == 0 || 64 - n.leading_zeros() - n.trailing_zeros() <= f64::MANTISSA_DIGITS => (errors.len() == reported).then_some(n as f64),
+            Ok(_) => {
+                errors.push(ScanError::new(map.span(start, *i + 1), &("integer literal '".to_owned() + &text + "' can't be represented exactly")));
+                None
+            },
+            Err(_) => {
+                errors.push(ScanError::new(map.span(start, *i + 1), &("integer literal '".to_owned() + &text + "' is too large")));
+                None
+            },
+        };
+    }
+
+    digits(source, start, i, char::is_ascii_digit, map, errors);
+
+    if *i + 1 < source.len() && source[*i + 1] == '.' {
+        *i += 1;
+        if *i + 1 < source.len() && source[*i + 1].is_ascii_digit() {
+            digits(source, start, i, char::is_ascii_digit, map, errors);
+        } else {
+            errors.push(ScanError::new(map.span(start, *i + 1), &("expected digits after the decimal point in '".to_owned() + &SourceMap::text(source, start, *i + 1) + "'")));
+        }
+    }
+
+    if *i + 1 < source.len() && (source[*i + 1] == 'e' || source[*i + 1] == 'E') {
+        *i += 1;
+        if *i + 1 < source.len() && (source[*i + 1] == '+' || source[*i + 1] == '-') {
+            *i += 1;
+        }
+        if *i + 1 < source.len() && source[*i + 1].is_ascii_digit() {
+            digits(source, start, i, char::is_ascii_digit, map, errors);
+        } else {
+            errors.push(ScanError::new(map.span(start, *i + 1), &("expected digits in the exponent of '".to_owned() + &SourceMap::text(source, start, *i + 1) + "'")));
+        }
+    }
+
+    if errors.len() > reported {
+        return None;
+    }
+    let value = source[start..=*i].iter().filter(|c| **c != '_').collect::<String>().parse::<f64>().ok()?;
+    if !value.is_finite() {
+        errors.push(ScanError::new(map.span(start, *i + 1), "number literal is out of range"));
+        return None;
+    }
+    Some(value)
+}
+
+fn digits(source: &[char], start: usize, i: &mut usize, is_digit: fn(&char) -> bool, map: &SourceMap, errors: &mut Vec<ScanError>) {
+    while *i + 1 < source.len() && (is_digit(&source[*i + 1]) || source[*i + 1] == '_') {
+        *i += 1;
+    }
+
+    if source[*i] == '_' {
+        errors.push(ScanError::new(map.span(start, *i + 1), &("digit separator '_' must be followed by a digit in '".to_owned() + &SourceMap::text(source, start, *i + 1) + "'")));
+    }
+}
+
+/// How a string segment ended.
+enum StringEnd {
+    Quote,
+    /// At a `${`, with `i` on the `{`.
+    Interpolation,
+    Eof,
+}
+
+fn string(source: &[char], i: &mut usize, map: &SourceMap, errors: &mut Vec<ScanError>) -> (String, StringEnd) {
+    let mut value = String::new();
+
+    while *i + 1 < source.len() && source[*i + 1] != '"' {
+        *i += 1;
+        if source[*i] == '$' && source.get(*i + 1) == Some(&'{') {
+            *i += 1;
+            return (value, StringEnd::Interpolation);
+        } else if source[*i] == '\\' {
+            if let Some(c) = escape(source, i, map, errors) {
+                value.push(c);
+            }
+        } else {
+            value.push(source[*i]);
+        }
+    }
+
+    if *i + 1 >= source.len() {
+        return (value, StringEnd::Eof);
+    }
+    *i += 1;
+    (value, StringEnd::Quote)
+}
+
+fn escape(source: &[char], i: &mut usize, map: &SourceMap, errors: &mut Vec<ScanError>) -> Option<char> {
+    let start = *i;
+    let c = *source.get(*i + 1)?;
+    *i += 1;
+
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '"' | '\'' | '$' => Some(c),
+        'u' => {
+            if source.get(*i + 1) != Some(&'{') {
+                errors.push(ScanError::new(map.span(start, *i + 1), "expected '{' after '\\u' in unicode escape"));
+                return None;
+            }
+            *i += 1;
+
+            let mut digits = String::new();
+            while *i + 1 < source.len() && source[*i + 1] != '}' && source[*i + 1] != '"' {
+                *i += 1;
+                digits.push(source[*i]);
+            }
+            if source.get(*i + 1) != Some(&'}') {
+                errors.push(ScanError::new(map.span(start, *i + 1), "expected '}' to close unicode escape"));
+                return None;
+            }
+            *i += 1;
+
+            let code = if digits.is_empty() || digits.len() > 6 {
+                None
+            } else {
+                u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32)
+            };
+            if code.is_none() {
+                errors.push(ScanError::new(map.span(start, *i + 1), &("invalid unicode escape '\\u{".to_owned() + &digits + "}'")));
+            }
+            code
+        },
+        _ => {
+            errors.push(ScanError::new(map.span(start, *i + 1), &("unknown escape sequence '\\".to_owned() + &c.to_string() + "'")));
+            None
+        },
+    }
+}
+
+fn raw_string(source: &[char], i: &mut usize, map: &SourceMap, errors: &mut Vec<ScanError>) -> String {
+    let start = *i;
+    let mut hashes = 0;
+    while *i + 1 < source.len() && source[*i + 1] == '#' {
+        *i += 1;
+        hashes += 1;
+    }
+
+    if source.get(*i + 1) != Some(&'"') {
+        errors.push(ScanError::new(map.span(start, *i + 1), "expected '\"' to start raw string"));
+        return String::new();
+    }
+    *i += 1;
+
+    let mut value = String::new();
+    loop {
+        if *i + 1 >= source.len() {
+            errors.push(ScanError::new(map.span(start, *i + 1), "unterminated raw string"));
+            return value;
+        }
+        *i += 1;
+
+        if source[*i] == '"' && source[*i + 1..].iter().take(hashes).filter(|c| **c == '#').count() == hashes {
+            *i += hashes;
+            return value;
+        }
+        value.push(source[*i]);
+    }
+}
+
+fn block_comment(source: &[char], i: &mut usize, map: &SourceMap, errors: &mut Vec<ScanError>) {
+    let start = *i;
+    let mut depth = 1;
+    *i += 1;
+
+    while depth > 0 {
+        if *i + 1 >= source.len() {
+            errors.push(ScanError::new(map.span(start, start + 2), "unterminated block comment"));
+            return;
+        }
+        *i += 1;
+
+        match (source[*i], source.get(*i + 1)) {
+            ('/', Some('*')) => {
+                depth += 1;
+                *i += 1;
+            },
+            ('*', Some('/')) => {
+                depth -= 1;
+                *i += 1;
             },
-            _ => error(line, word_index, &("unexpected token '".to_owned() + &source[i].to_string() + "'")),
+            _ => {},
         }
-        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str, trivia: bool) -> (Vec<Token>, Vec<ScanError>) {
+        let mut lexer = Lexer::new(source).with_trivia(trivia);
+        let tokens = lexer.by_ref().collect();
+        (tokens, lexer.errors().to_vec())
     }
 
-    tokens.push(Token::new(TokenType::EOF, "".to_string(), vec![], line, word_index));
+    fn types(source: &str) -> Vec<TokenType> {
+        lex(source, false).0.iter().map(|token| token.token_type).collect()
+    }
 
-    tokens
-}
\ No newline at end of file
+    #[test]
+    fn decimal_and_scientific_numbers() {
+        for (source, value) in [("0", 0.0), ("42", 42.0), ("1.5", 1.5), ("1_000.25", 1000.25), ("1e3", 1000.0), ("2.5E-2", 0.025), ("7e+1", 70.0), ("1e-999", 0.0), ("1.7976931348623157e308", f64::MAX)] {
+            let (tokens, errors) = lex(source, false);
+            assert!(errors.is_empty(), "{source}: {errors:?}");
+            assert_eq!(tokens[0].token_type, TokenType::NUMBER, "{source}");
+            assert_eq!(tokens[0].literal, Literal::Number(value), "{source}");
+            assert_eq!(tokens[0].lexeme, source);
+        }
+    }
+
+    #[test]
+    fn malformed_numbers_are_errors_not_values() {
+        for (source, message) in [
+            ("1.", "expected digits after the decimal point in '1.'"),
+            ("1.x", "expected digits after the decimal point in '1.'"),
+            ("1e", "expected digits in the exponent of '1e'"),
+            ("1e+", "expected digits in the exponent of '1e+'"),
+            ("1_", "digit separator '_' must be followed by a digit in '1_'"),
+            ("1e999", "number literal is out of range"),
+        ] {
+            let (tokens, errors) = lex(source, true);
+            assert_eq!(errors.len(), 1, "{source}: {errors:?}");
+            assert_eq!(errors[0].message, message);
+            assert_eq!(tokens[0].token_type, TokenType::ERROR, "{source}");
+            assert!(tokens.iter().all(|token| token.token_type != TokenType::NUMBER), "{source}");
+        }
+    }
+
+    #[test]
+    fn radix_literals_and_digit_separators() {
+        for (source, value) in [("0xFF", 255.0), ("0XfF", 255.0), ("0b1010_1010", 170.0), ("0o777", 511.0), ("1_000_000", 1_000_000.0), ("0x0", 0.0), ("0x1F_FFFF_FFFF_FFFF", 9_007_199_254_740_991.0), ("0x8000_0000_0000_0000", 9_223_372_036_854_775_808.0), ("0xFFFF_FFFF_FFFF_F800", 18_446_744_073_709_549_568.0)] {
+            let (tokens, errors) = lex(source, false);
+            assert!(errors.is_empty(), "{source}: {errors:?}");
+            assert_eq!(tokens[0].literal, Literal::Number(value), "{source}");
+            assert_eq!(tokens[0].lexeme, source);
+        }
+    }
+
+    #[test]
+    fn malformed_radix_literals() {
+        for (source, message) in [
+            ("0x", "expected digits after the prefix of '0x'"),
+            ("0b102", "invalid digit '2' in base 2 literal '0b102'"),
+            ("0o8", "invalid digit '8' in base 8 literal '0o8'"),
+            ("0x1_", "digit separator '_' must be followed by a digit in '0x1_'"),
+            ("0x1_0000_0000_0000_0000", "integer literal '0x1_0000_0000_0000_0000' is too large"),
+            ("0x1F_FFFF_FFFF_FFFF_F", "integer literal '0x1F_FFFF_FFFF_FFFF_F' can't be represented exactly"),
+            ("0xFFFF_FFFF_FFFF_FFFF", "integer literal '0xFFFF_FFFF_FFFF_FFFF' can't be represented exactly"),
+        ] {
+            let (tokens, errors) = lex(source, false);
+            assert_eq!(errors.len(), 1, "{source}: {errors:?}");
+            assert_eq!(errors[0].message, message);
+            assert!(tokens.iter().all(|token| token.token_type != TokenType::NUMBER), "{source}");
+        }
+    }
+
+    fn string_value(source: &str) -> (Literal, Vec<ScanError>) {
+        let (tokens, errors) = lex(source, false);
+        (tokens[0].literal.clone(), errors)
+    }
+
+    #[test]
+    fn string_escapes() {
+        let (value, errors) = string_value(r#""a\nb\t\r\0 \\ \" \' \$ \u{1F600} \u{e9}""#);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(value, Literal::String("a\nb\t\r\0 \\ \" ' $ \u{1F600} \u{e9}".to_string()));
+    }
+
+    #[test]
+    fn invalid_string_escapes() {
+        for (source, message) in [
+            (r#""\q""#, "unknown escape sequence '\\q'"),
+            (r#""\u41""#, "expected '{' after '\\u' in unicode escape"),
+            (r#""\u{41""#, "expected '}' to close unicode escape"),
+            (r#""\u{110000}""#, "invalid unicode escape '\\u{110000}'"),
+            (r#""\u{}""#, "invalid unicode escape '\\u{}'"),
+            ("\"open", "unterminated string"),
+        ] {
+            let (_, errors) = string_value(source);
+            assert_eq!(errors.len(), 1, "{source}: {errors:?}");
+            assert_eq!(errors[0].message, message);
+        }
+    }
+
+    #[test]
+    fn interpolation_segments() {
+        let (tokens, errors) = lex(r#""a${x}\${y}${"in${n}"}""#, false);
+        assert!(errors.is_empty(), "{errors:?}");
+        let kinds: Vec<(TokenType, &str)> = tokens.iter().map(|token| (token.token_type, token.lexeme.as_str())).collect();
+        assert_eq!(kinds, [
+            (TokenType::INTERPOLATION, "\"a${"),
+            (TokenType::IDENTIFIER, "x"),
+            (TokenType::INTERPOLATION, "}\\${y}${"),
+            (TokenType::INTERPOLATION, "\"in${"),
+            (TokenType::IDENTIFIER, "n"),
+            (TokenType::STRING, "}\""),
+            (TokenType::STRING, "}\""),
+            (TokenType::EOF, ""),
+        ]);
+        assert_eq!(tokens[2].literal, Literal::String("${y}".to_string()));
+    }
+
+    #[test]
+    fn unterminated_interpolation_points_at_its_opening() {
+        let (_, errors) = lex("print \"${1\"\nprint 2\n", false);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(errors[0].message, "unterminated interpolation: expected '}' before the closing quote");
+        assert_eq!((errors[0].span.line, errors[0].span.column, errors[0].span.end - errors[0].span.start), (1, 8, 2));
+    }
+
+    #[test]
+    fn raw_strings_keep_backslashes_and_quotes() {
+        assert_eq!(string_value(r#"r"a\nb""#).0, Literal::String("a\\nb".to_string()));
+        assert_eq!(string_value("r#\"say \"hi\"\"#").0, Literal::String("say \"hi\"".to_string()));
+        assert_eq!(string_value("r#\"open").1[0].message, "unterminated raw string");
+    }
+
+    #[test]
+    fn positions_after_a_multi_line_string() {
+        let (tokens, _) = lex("\"one\ntwo\nthree\" x", false);
+        assert_eq!(tokens[0].literal, Literal::String("one\ntwo\nthree".to_string()));
+        assert_eq!((tokens[1].span.line, tokens[1].span.column), (3, 8));
+    }
+
+    #[test]
+    fn comments_emit_no_tokens() {
+        assert_eq!(types("a /* one /* two */ still one */ b"), vec![TokenType::IDENTIFIER, TokenType::IDENTIFIER, TokenType::EOF]);
+        assert_eq!(types("a // b / c\n"), vec![TokenType::IDENTIFIER, TokenType::SEMICOLON, TokenType::EOF]);
+        assert_eq!(types("4 / 2"), vec![TokenType::NUMBER, TokenType::SLASH, TokenType::NUMBER, TokenType::EOF]);
+    }
+
+    #[test]
+    fn block_comments_span_lines() {
+        let (tokens, errors) = lex("/* a\n /* b\n */\n*/ x", false);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(tokens[0].lexeme, "x");
+        assert_eq!((tokens[0].span.line, tokens[0].span.column), (4, 4));
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let (tokens, errors) = lex("x /* a /* b */", false);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert_eq!(errors[0].message, "unterminated block comment");
+        assert_eq!((errors[0].span.start, errors[0].span.end), (2, 4));
+        assert_eq!(tokens.len(), 2);
+    }
+
+    #[test]
+    fn spans_are_byte_offsets_with_character_columns() {
+        let (tokens, _) = lex("let é = 1\n  é+2", false);
+        let positions: Vec<(&str, usize, usize, usize, usize)> = tokens
+            .iter()
+            .map(|token| (token.lexeme.as_str(), token.span.start, token.span.end, token.span.line, token.span.column))
+            .collect();
+        assert_eq!(positions, vec![
+            ("let", 0, 3, 1, 1),
+            ("é", 4, 6, 1, 5),
+            ("=", 7, 8, 1, 7),
+            ("1", 9, 10, 1, 9),
+            ("\n", 10, 11, 1, 10),
+            ("é", 13, 15, 2, 3),
+            ("+", 15, 16, 2, 4),
+            ("2", 16, 17, 2, 5),
+            ("", 17, 17, 2, 6),
+        ]);
+    }
+
+    #[test]
+    fn spans_record_where_they_end() {
+        let (tokens, _) = lex("\"one\ntwo\" é", false);
+        let ends: Vec<(usize, usize, usize, usize)> = tokens
+            .iter()
+            .map(|token| (token.span.line, token.span.column, token.span.end_line, token.span.end_column))
+            .collect();
+        assert_eq!(ends, vec![(1, 1, 2, 5), (2, 6, 2, 7), (2, 7, 2, 7)]);
+        assert_eq!(tokens[0].span.to(tokens[1].span).to_string(), "[1:1] to [2:7]");
+        assert_eq!(tokens[2].span.to_string(), "[2:7]");
+    }
+
+    #[test]
+    fn unicode_and_alphanumeric_identifiers() {
+        for name in ["x1", "player2", "größe", "_tmp", "変数", "lettuce", "classy"] {
+            let (tokens, errors) = lex(name, false);
+            assert!(errors.is_empty(), "{name}: {errors:?}");
+            assert_eq!((tokens[0].token_type, tokens[0].lexeme.as_str()), (TokenType::IDENTIFIER, name));
+        }
+        assert_eq!(types("let fn null"), vec![TokenType::VAR, TokenType::FUN, TokenType::NIL, TokenType::EOF]);
+        assert_eq!(types("2x"), vec![TokenType::NUMBER, TokenType::IDENTIFIER, TokenType::EOF]);
+    }
+
+    #[test]
+    fn identifier_at_end_of_input() {
+        let (tokens, errors) = lex("a", false);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(tokens[0].lexeme, "a");
+        assert_eq!(tokens[1].token_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn trivia_round_trips_every_byte() {
+        let source = "let π = 0x_1F /* a /* nested */ comment */ + 1.5e3 // tail\r\n\tprint \"${π} é\" + r#\"raw \"q\"\"#\n@ {\n}";
+        let (tokens, _) = lex(source, true);
+        let text: String = tokens.iter().map(|token| token.lexeme.as_str()).collect();
+        assert_eq!(text, source);
+
+        let mut end = 0;
+        for token in &tokens {
+            assert_eq!(token.span.start, end, "gap before {token:?}");
+            assert_eq!(&source[token.span.start..token.span.end], token.lexeme);
+            end = token.span.end;
+        }
+        assert_eq!(end, source.len());
+        assert_eq!(tokens.last().map(|token| token.token_type), Some(TokenType::EOF));
+    }
+
+    #[test]
+    fn trivia_keeps_comments_whitespace_and_newlines() {
+        let (tokens, _) = lex("a // c\n/* d */ b", true);
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(types, vec![
+            TokenType::IDENTIFIER,
+            TokenType::WHITESPACE,
+            TokenType::LINE_COMMENT,
+            TokenType::NEWLINE,
+            TokenType::BLOCK_COMMENT,
+            TokenType::WHITESPACE,
+            TokenType::IDENTIFIER,
+            TokenType::EOF,
+        ]);
+    }
+
+    #[test]
+    fn diagnostics_are_collected_not_printed() {
+        let (tokens, errors) = lex("let a = @", true);
+        let error = tokens.iter().find(|token| token.token_type == TokenType::ERROR).expect("an ERROR token");
+        assert_eq!(error.lexeme, "@");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "unexpected token '@'");
+        assert_eq!(errors[0].span, error.span);
+    }
+
+    #[test]
+    fn without_trivia_newlines_end_statements_only_where_one_can_end() {
+        assert_eq!(types("a\nb"), vec![TokenType::IDENTIFIER, TokenType::SEMICOLON, TokenType::IDENTIFIER, TokenType::EOF]);
+        assert_eq!(types("a +\nb"), vec![TokenType::IDENTIFIER, TokenType::PLUS, TokenType::IDENTIFIER, TokenType::EOF]);
+        assert_eq!(types("(a\n)"), vec![TokenType::LEFT_PAREN, TokenType::IDENTIFIER, TokenType::RIGHT_PAREN, TokenType::EOF]);
+        assert_eq!(types("a // c\n"), vec![TokenType::IDENTIFIER, TokenType::SEMICOLON, TokenType::EOF]);
+    }
+}
@@ -1,11 +1,17 @@
-#[derive(Debug, Clone, PartialEq)]
-#[allow(non_camel_case_types)]
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single-character tokens.
     LEFT_PAREN,
     RIGHT_PAREN,
     LEFT_BRACE,
     RIGHT_BRACE,
+    LEFT_BRACKET,
+    RIGHT_BRACKET,
     COMMA,
     DOT,
     MINUS,
@@ -13,20 +19,30 @@ pub enum TokenType {
     SEMICOLON,
     SLASH,
     STAR,
+    PERCENT,
 
     // One or two character tokens.
     BANG,
     BANG_EQUAL,
     EQUAL,
     EQUAL_EQUAL,
+    ARROW,
     GREATER,
     GREATER_EQUAL,
     LESS,
     LESS_EQUAL,
+    MINUS_EQUAL,
+    MINUS_MINUS,
+    PLUS_EQUAL,
+    PLUS_PLUS,
+    SLASH_EQUAL,
+    STAR_EQUAL,
+    PERCENT_EQUAL,
 
     // Literals.
     IDENTIFIER,
     STRING,
+    INTERPOLATION,
     NUMBER,
 
     // Keywords.
@@ -47,32 +63,92 @@ pub enum TokenType {
     VAR,
     WHILE,
 
+    // Trivia, only produced by a lexer with trivia enabled.
+    WHITESPACE,
+    NEWLINE,
+    LINE_COMMENT,
+    BLOCK_COMMENT,
+    ERROR,
+
     EOF,
 }
 
-#[derive(Debug, Clone)]
+impl TokenType {
+    pub fn ends_statement(self) -> bool {
+        matches!(self, TokenType::IDENTIFIER | TokenType::STRING | TokenType::NUMBER | TokenType::TRUE | TokenType::FALSE | TokenType::NIL | TokenType::THIS | TokenType::SUPER | TokenType::RETURN | TokenType::RIGHT_PAREN | TokenType::RIGHT_BRACE | TokenType::RIGHT_BRACKET | TokenType::PLUS_PLUS | TokenType::MINUS_MINUS)
+    }
+
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenType::WHITESPACE | TokenType::NEWLINE | TokenType::LINE_COMMENT | TokenType::BLOCK_COMMENT | TokenType::ERROR)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub enum Literal {
+    Number(f64),
+    String(String),
+    None,
+}
+
+/// A source range: byte offsets with the end exclusive, and the 1-based
+/// line and character column of each end.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    pub fn to(self, other: Span) -> Span {
+        let first = if self.start <= other.start { self } else { other };
+        let last = if self.end >= other.end { self } else { other };
+        Span {
+            start: first.start,
+            end: last.end,
+            line: first.line,
+            column: first.column,
+            end_line: last.end_line,
+            end_column: last.end_column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "[{}:{}]", self.line, self.column)
+        } else {
+            write!(f, "[{}:{}] to [{}:{}]", self.line, self.column, self.end_line, self.end_column)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_field_names)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
-    pub literal: Vec<char>,
-    pub line: usize,
-    pub index: usize,
+    pub literal: Literal,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(
         token_type: TokenType,
         lexeme: String,
-        literal: Vec<char>,
-        line: usize,
-        index: usize,
+        literal: Literal,
+        span: Span,
     ) -> Token {
         Token {
             token_type,
             lexeme,
             literal,
-            line,
-            index,
+            span,
         }
     }
 }
@@ -0,0 +1,654 @@
+use std::rc::Rc;
+
+use crate::{token::Token, environment::{Expression, FunctionDeclaration, Slot, Statement}};
+
+/// Read-only traversal of the tree. Every method defaults to walking its
+/// children, so an implementation only overrides the nodes it cares about.
+pub trait Visitor {
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expr: &Expression) {
+        walk_expression(self, expr);
+    }
+
+    fn visit_function(&mut self, declaration: &FunctionDeclaration) {
+        walk_function(self, declaration);
+    }
+
+    fn visit_expression_statement(&mut self, expr: &Expression) {
+        self.visit_expression(expr);
+    }
+
+    fn visit_print(&mut self, expr: &Expression) {
+        self.visit_expression(expr);
+    }
+
+    fn visit_var(&mut self, _name: &Token, initializer: &Expression) {
+        self.visit_expression(initializer);
+    }
+
+    fn visit_block(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn visit_if(&mut self, condition: &Expression, then_branch: &Statement, else_branch: Option<&Statement>) {
+        self.visit_expression(condition);
+        self.visit_statement(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.visit_statement(else_branch);
+        }
+    }
+
+    fn visit_while(&mut self, condition: &Expression, body: &Statement) {
+        self.visit_expression(condition);
+        self.visit_statement(body);
+    }
+
+    fn visit_function_statement(&mut self, declaration: &FunctionDeclaration) {
+        self.visit_function(declaration);
+    }
+
+    fn visit_return(&mut self, _keyword: &Token, value: Option<&Expression>) {
+        if let Some(value) = value {
+            self.visit_expression(value);
+        }
+    }
+
+    fn visit_class(&mut self, _name: &Token, superclass: Option<&Expression>, methods: &[Rc<FunctionDeclaration>]) {
+        if let Some(superclass) = superclass {
+            self.visit_expression(superclass);
+        }
+        for method in methods {
+            self.visit_function(method);
+        }
+    }
+
+    fn visit_literal(&mut self, _value: &Token) {}
+
+    fn visit_unary(&mut self, _op: &Token, right: &Expression) {
+        self.visit_expression(right);
+    }
+
+    fn visit_binary(&mut self, left: &Expression, _op: &Token, right: &Expression) {
+        self.visit_expression(left);
+        self.visit_expression(right);
+    }
+
+    fn visit_logical(&mut self, left: &Expression, _op: &Token, right: &Expression) {
+        self.visit_expression(left);
+        self.visit_expression(right);
+    }
+
+    fn visit_grouping(&mut self, expr: &Expression) {
+        self.visit_expression(expr);
+    }
+
+    fn visit_variable(&mut self, _name: &Token, _slot: Option<Slot>) {}
+
+    fn visit_interpolation(&mut self, parts: &[Expression]) {
+        for part in parts {
+            self.visit_expression(part);
+        }
+    }
+
+    fn visit_call(&mut self, callee: &Expression, _paren: &Token, arguments: &[Expression]) {
+        self.visit_expression(callee);
+        for argument in arguments {
+            self.visit_expression(argument);
+        }
+    }
+
+    fn visit_assign(&mut self, _name: &Token, value: &Expression, _slot: Option<Slot>) {
+        self.visit_expression(value);
+    }
+
+    fn visit_lambda(&mut self, declaration: &FunctionDeclaration) {
+        self.visit_function(declaration);
+    }
+
+    fn visit_compound_assign(&mut self, _name: &Token, _op: &Token, value: &Expression, _slot: Option<Slot>) {
+        self.visit_expression(value);
+    }
+
+    fn visit_update(&mut self, _name: &Token, _op: &Token, _prefix: bool, _slot: Option<Slot>) {}
+
+    fn visit_get(&mut self, object: &Expression, _name: &Token) {
+        self.visit_expression(object);
+    }
+
+    fn visit_index(&mut self, object: &Expression, _bracket: &Token, index: &Expression) {
+        self.visit_expression(object);
+        self.visit_expression(index);
+    }
+
+    fn visit_set(&mut self, object: &Expression, _name: &Token, value: &Expression) {
+        self.visit_expression(object);
+        self.visit_expression(value);
+    }
+
+    fn visit_this(&mut self, _keyword: &Token, _slot: Option<Slot>) {}
+
+    fn visit_super(&mut self, _keyword: &Token, _method: &Token, _slot: Option<Slot>) {}
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Expression(expr) => visitor.visit_expression_statement(expr),
+        Statement::Print(expr) => visitor.visit_print(expr),
+        Statement::Var(name, initializer) => visitor.visit_var(name, initializer),
+        Statement::Block(statements) => visitor.visit_block(statements),
+        Statement::If(condition, then_branch, else_branch) => visitor.visit_if(condition, then_branch, else_branch.as_deref()),
+        Statement::While(condition, body) => visitor.visit_while(condition, body),
+        Statement::Function(declaration) => visitor.visit_function_statement(declaration),
+        Statement::Return(keyword, value) => visitor.visit_return(keyword, value.as_ref()),
+        Statement::Class(name, superclass, methods) => visitor.visit_class(name, superclass.as_ref(), methods),
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expression) {
+    match expr {
+        Expression::Literal(value) => visitor.visit_literal(value),
+        Expression::Unary(op, right) => visitor.visit_unary(op, right),
+        Expression::Binary(left, op, right) => visitor.visit_binary(left, op, right),
+        Expression::Logical(left, op, right) => visitor.visit_logical(left, op, right),
+        Expression::Grouping(expr) => visitor.visit_grouping(expr),
+        Expression::Variable(name, slot) => visitor.visit_variable(name, *slot),
+        Expression::Interpolation(parts) => visitor.visit_interpolation(parts),
+        Expression::Call(callee, paren, arguments) => visitor.visit_call(callee, paren, arguments),
+        Expression::Assign(name, value, slot) => visitor.visit_assign(name, value, *slot),
+        Expression::Lambda(declaration) => visitor.visit_lambda(declaration),
+        Expression::CompoundAssign(name, op, value, slot) => visitor.visit_compound_assign(name, op, value, *slot),
+        Expression::Update(name, op, prefix, slot) => visitor.visit_update(name, op, *prefix, *slot),
+        Expression::Get(object, name) => visitor.visit_get(object, name),
+        Expression::Index(object, bracket, index) => visitor.visit_index(object, bracket, index),
+        Expression::Set(object, name, value) => visitor.visit_set(object, name, value),
+        Expression::This(keyword, slot) => visitor.visit_this(keyword, *slot),
+        Expression::Super(keyword, method, slot) => visitor.visit_super(keyword, method, *slot),
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, declaration: &FunctionDeclaration) {
+    for statement in &declaration.body {
+        visitor.visit_statement(statement);
+    }
+}
+
+/// In-place traversal of the tree, for passes that rewrite nodes without
+/// rebuilding it. Shared function declarations are copied on write.
+pub trait VisitorMut {
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expr: &mut Expression) {
+        walk_expression_mut(self, expr);
+    }
+
+    fn visit_function_mut(&mut self, declaration: &mut FunctionDeclaration) {
+        walk_function_mut(self, declaration);
+    }
+
+    fn visit_expression_statement_mut(&mut self, expr: &mut Expression) {
+        self.visit_expression_mut(expr);
+    }
+
+    fn visit_print_mut(&mut self, expr: &mut Expression) {
+        self.visit_expression_mut(expr);
+    }
+
+    fn visit_var_mut(&mut self, _name: &mut Token, initializer: &mut Expression) {
+        self.visit_expression_mut(initializer);
+    }
+
+    fn visit_block_mut(&mut self, statements: &mut Vec<Statement>) {
+        for statement in statements {
+            self.visit_statement_mut(statement);
+        }
+    }
+
+    fn visit_if_mut(&mut self, condition: &mut Expression, then_branch: &mut Statement, else_branch: Option<&mut Statement>) {
+        self.visit_expression_mut(condition);
+        self.visit_statement_mut(then_branch);
+        if let Some(else_branch) = else_branch {
+            self.visit_statement_mut(else_branch);
+        }
+    }
+
+    fn visit_while_mut(&mut self, condition: &mut Expression, body: &mut Statement) {
+        self.visit_expression_mut(condition);
+        self.visit_statement_mut(body);
+    }
+
+    fn visit_function_statement_mut(&mut self, declaration: &mut FunctionDeclaration) {
+        self.visit_function_mut(declaration);
+    }
+
+    fn visit_return_mut(&mut self, _keyword: &mut Token, value: Option<&mut Expression>) {
+        if let Some(value) = value {
+            self.visit_expression_mut(value);
+        }
+    }
+
+    fn visit_class_mut(&mut self, _name: &mut Token, superclass: Option<&mut Expression>, methods: &mut Vec<Rc<FunctionDeclaration>>) {
+        if let Some(superclass) = superclass {
+            self.visit_expression_mut(superclass);
+        }
+        for method in methods {
+            self.visit_function_mut(Rc::make_mut(method));
+        }
+    }
+
+    fn visit_literal_mut(&mut self, _value: &mut Token) {}
+
+    fn visit_unary_mut(&mut self, _op: &mut Token, right: &mut Expression) {
+        self.visit_expression_mut(right);
+    }
+
+    fn visit_binary_mut(&mut self, left: &mut Expression, _op: &mut Token, right: &mut Expression) {
+        self.visit_expression_mut(left);
+        self.visit_expression_mut(right);
+    }
+
+    fn visit_logical_mut(&mut self, left: &mut Expression, _op: &mut Token, right: &mut Expression) {
+        self.visit_expression_mut(left);
+        self.visit_expression_mut(right);
+    }
+
+    fn visit_grouping_mut(&mut self, expr: &mut Expression) {
+        self.visit_expression_mut(expr);
+    }
+
+    fn visit_variable_mut(&mut self, _name: &mut Token, _slot: &mut Option<Slot>) {}
+
+    fn visit_interpolation_mut(&mut self, parts: &mut Vec<Expression>) {
+        for part in parts {
+            self.visit_expression_mut(part);
+        }
+    }
+
+    fn visit_call_mut(&mut self, callee: &mut Expression, _paren: &mut Token, arguments: &mut Vec<Expression>) {
+        self.visit_expression_mut(callee);
+        for argument in arguments {
+            self.visit_expression_mut(argument);
+        }
+    }
+
+    fn visit_assign_mut(&mut self, _name: &mut Token, value: &mut Expression, _slot: &mut Option<Slot>) {
+        self.visit_expression_mut(value);
+    }
+
+    fn visit_lambda_mut(&mut self, declaration: &mut FunctionDeclaration) {
+        self.visit_function_mut(declaration);
+    }
+
+    fn visit_compound_assign_mut(&mut self, _name: &mut Token, _op: &mut Token, value: &mut Expression, _slot: &mut Option<Slot>) {
+        self.visit_expression_mut(value);
+    }
+
+    fn visit_update_mut(&mut self, _name: &mut Token, _op: &mut Token, _prefix: &mut bool, _slot: &mut Option<Slot>) {}
+
+    fn visit_get_mut(&mut self, object: &mut Expression, _name: &mut Token) {
+        self.visit_expression_mut(object);
+    }
+
+    fn visit_index_mut(&mut self, object: &mut Expression, _bracket: &mut Token, index: &mut Expression) {
+        self.visit_expression_mut(object);
+        self.visit_expression_mut(index);
+    }
+
+    fn visit_set_mut(&mut self, object: &mut Expression, _name: &mut Token, value: &mut Expression) {
+        self.visit_expression_mut(object);
+        self.visit_expression_mut(value);
+    }
+
+    fn visit_this_mut(&mut self, _keyword: &mut Token, _slot: &mut Option<Slot>) {}
+
+    fn visit_super_mut(&mut self, _keyword: &mut Token, _method: &mut Token, _slot: &mut Option<Slot>) {}
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::Expression(expr) => visitor.visit_expression_statement_mut(expr),
+        Statement::Print(expr) => visitor.visit_print_mut(expr),
+        Statement::Var(name, initializer) => visitor.visit_var_mut(name, initializer),
+        Statement::Block(statements) => visitor.visit_block_mut(statements),
+        Statement::If(condition, then_branch, else_branch) => visitor.visit_if_mut(condition, then_branch, else_branch.as_deref_mut()),
+        Statement::While(condition, body) => visitor.visit_while_mut(condition, body),
+        Statement::Function(declaration) => visitor.visit_function_statement_mut(Rc::make_mut(declaration)),
+        Statement::Return(keyword, value) => visitor.visit_return_mut(keyword, value.as_mut()),
+        Statement::Class(name, superclass, methods) => visitor.visit_class_mut(name, superclass.as_mut(), methods),
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expression) {
+    match expr {
+        Expression::Literal(value) => visitor.visit_literal_mut(value),
+        Expression::Unary(op, right) => visitor.visit_unary_mut(op, right),
+        Expression::Binary(left, op, right) => visitor.visit_binary_mut(left, op, right),
+        Expression::Logical(left, op, right) => visitor.visit_logical_mut(left, op, right),
+        Expression::Grouping(expr) => visitor.visit_grouping_mut(expr),
+        Expression::Variable(name, slot) => visitor.visit_variable_mut(name, slot),
+        Expression::Interpolation(parts) => visitor.visit_interpolation_mut(parts),
+        Expression::Call(callee, paren, arguments) => visitor.visit_call_mut(callee, paren, arguments),
+        Expression::Assign(name, value, slot) => visitor.visit_assign_mut(name, value, slot),
+        Expression::Lambda(declaration) => visitor.visit_lambda_mut(Rc::make_mut(declaration)),
+        Expression::CompoundAssign(name, op, value, slot) => visitor.visit_compound_assign_mut(name, op, value, slot),
+        Expression::Update(name, op, prefix, slot) => visitor.visit_update_mut(name, op, prefix, slot),
+        Expression::Get(object, name) => visitor.visit_get_mut(object, name),
+        Expression::Index(object, bracket, index) => visitor.visit_index_mut(object, bracket, index),
+        Expression::Set(object, name, value) => visitor.visit_set_mut(object, name, value),
+        Expression::This(keyword, slot) => visitor.visit_this_mut(keyword, slot),
+        Expression::Super(keyword, method, slot) => visitor.visit_super_mut(keyword, method, slot),
+    }
+}
+
+pub fn walk_function_mut<V: VisitorMut + ?Sized>(visitor: &mut V, declaration: &mut FunctionDeclaration) {
+    for statement in &mut declaration.body {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+/// Owning traversal that rebuilds the tree, for passes that replace nodes
+/// with different variants. Every method defaults to folding its children
+/// and reassembling the same variant. No pass needs it yet outside the tests.
+#[cfg_attr(not(test), allow(dead_code))]
+pub trait Fold {
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        fold_statement(self, statement)
+    }
+
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        fold_expression(self, expr)
+    }
+
+    fn fold_function(&mut self, declaration: FunctionDeclaration) -> FunctionDeclaration {
+        fold_function(self, declaration)
+    }
+
+    fn fold_expression_statement(&mut self, expr: Expression) -> Statement {
+        Statement::Expression(self.fold_expression(expr))
+    }
+
+    fn fold_print(&mut self, expr: Expression) -> Statement {
+        Statement::Print(self.fold_expression(expr))
+    }
+
+    fn fold_var(&mut self, name: Token, initializer: Expression) -> Statement {
+        Statement::Var(name, self.fold_expression(initializer))
+    }
+
+    fn fold_block(&mut self, statements: Vec<Statement>) -> Statement {
+        Statement::Block(statements.into_iter().map(|statement| self.fold_statement(statement)).collect())
+    }
+
+    fn fold_if(&mut self, condition: Expression, then_branch: Statement, else_branch: Option<Statement>) -> Statement {
+        let condition = self.fold_expression(condition);
+        let then_branch = self.fold_statement(then_branch);
+        let else_branch = else_branch.map(|else_branch| Box::new(self.fold_statement(else_branch)));
+        Statement::If(condition, Box::new(then_branch), else_branch)
+    }
+
+    fn fold_while(&mut self, condition: Expression, body: Statement) -> Statement {
+        let condition = self.fold_expression(condition);
+        Statement::While(condition, Box::new(self.fold_statement(body)))
+    }
+
+    fn fold_function_statement(&mut self, declaration: FunctionDeclaration) -> Statement {
+        Statement::Function(Rc::new(self.fold_function(declaration)))
+    }
+
+    fn fold_return(&mut self, keyword: Token, value: Option<Expression>) -> Statement {
+        Statement::Return(keyword, value.map(|value| self.fold_expression(value)))
+    }
+
+    fn fold_class(&mut self, name: Token, superclass: Option<Expression>, methods: Vec<Rc<FunctionDeclaration>>) -> Statement {
+        let superclass = superclass.map(|superclass| self.fold_expression(superclass));
+        let methods = methods.into_iter().map(|method| Rc::new(self.fold_function(Rc::unwrap_or_clone(method)))).collect();
+        Statement::Class(name, superclass, methods)
+    }
+
+    fn fold_literal(&mut self, value: Token) -> Expression {
+        Expression::Literal(value)
+    }
+
+    fn fold_unary(&mut self, op: Token, right: Expression) -> Expression {
+        Expression::Unary(op, Box::new(self.fold_expression(right)))
+    }
+
+    fn fold_binary(&mut self, left: Expression, op: Token, right: Expression) -> Expression {
+        let left = self.fold_expression(left);
+        Expression::Binary(Box::new(left), op, Box::new(self.fold_expression(right)))
+    }
+
+    fn fold_logical(&mut self, left: Expression, op: Token, right: Expression) -> Expression {
+        let left = self.fold_expression(left);
+        Expression::Logical(Box::new(left), op, Box::new(self.fold_expression(right)))
+    }
+
+    fn fold_grouping(&mut self, expr: Expression) -> Expression {
+        Expression::Grouping(Box::new(self.fold_expression(expr)))
+    }
+
+    fn fold_variable(&mut self, name: Token, slot: Option<Slot>) -> Expression {
+        Expression::Variable(name, slot)
+    }
+
+    fn fold_interpolation(&mut self, parts: Vec<Expression>) -> Expression {
+        Expression::Interpolation(parts.into_iter().map(|part| self.fold_expression(part)).collect())
+    }
+
+    fn fold_call(&mut self, callee: Expression, paren: Token, arguments: Vec<Expression>) -> Expression {
+        let callee = self.fold_expression(callee);
+        let arguments = arguments.into_iter().map(|argument| self.fold_expression(argument)).collect();
+        Expression::Call(Box::new(callee), paren, arguments)
+    }
+
+    fn fold_assign(&mut self, name: Token, value: Expression, slot: Option<Slot>) -> Expression {
+        Expression::Assign(name, Box::new(self.fold_expression(value)), slot)
+    }
+
+    fn fold_lambda(&mut self, declaration: FunctionDeclaration) -> Expression {
+        Expression::Lambda(Rc::new(self.fold_function(declaration)))
+    }
+
+    fn fold_compound_assign(&mut self, name: Token, op: Token, value: Expression, slot: Option<Slot>) -> Expression {
+        Expression::CompoundAssign(name, op, Box::new(self.fold_expression(value)), slot)
+    }
+
+    fn fold_update(&mut self, name: Token, op: Token, prefix: bool, slot: Option<Slot>) -> Expression {
+        Expression::Update(name, op, prefix, slot)
+    }
+
+    fn fold_get(&mut self, object: Expression, name: Token) -> Expression {
+        Expression::Get(Box::new(self.fold_expression(object)), name)
+    }
+
+    fn fold_index(&mut self, object: Expression, bracket: Token, index: Expression) -> Expression {
+        let object = self.fold_expression(object);
+        Expression::Index(Box::new(object), bracket, Box::new(self.fold_expression(index)))
+    }
+
+    fn fold_set(&mut self, object: Expression, name: Token, value: Expression) -> Expression {
+        let object = self.fold_expression(object);
+        Expression::Set(Box::new(object), name, Box::new(self.fold_expression(value)))
+    }
+
+    fn fold_this(&mut self, keyword: Token, slot: Option<Slot>) -> Expression {
+        Expression::This(keyword, slot)
+    }
+
+    fn fold_super(&mut self, keyword: Token, method: Token, slot: Option<Slot>) -> Expression {
+        Expression::Super(keyword, method, slot)
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn fold_statement<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+    match statement {
+        Statement::Expression(expr) => folder.fold_expression_statement(expr),
+        Statement::Print(expr) => folder.fold_print(expr),
+        Statement::Var(name, initializer) => folder.fold_var(name, initializer),
+        Statement::Block(statements) => folder.fold_block(statements),
+        Statement::If(condition, then_branch, else_branch) => folder.fold_if(condition, *then_branch, else_branch.map(|else_branch| *else_branch)),
+        Statement::While(condition, body) => folder.fold_while(condition, *body),
+        Statement::Function(declaration) => folder.fold_function_statement(Rc::unwrap_or_clone(declaration)),
+        Statement::Return(keyword, value) => folder.fold_return(keyword, value),
+        Statement::Class(name, superclass, methods) => folder.fold_class(name, superclass, methods),
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn fold_expression<F: Fold + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    match expr {
+        Expression::Literal(value) => folder.fold_literal(value),
+        Expression::Unary(op, right) => folder.fold_unary(op, *right),
+        Expression::Binary(left, op, right) => folder.fold_binary(*left, op, *right),
+        Expression::Logical(left, op, right) => folder.fold_logical(*left, op, *right),
+        Expression::Grouping(expr) => folder.fold_grouping(*expr),
+        Expression::Variable(name, slot) => folder.fold_variable(name, slot),
+        Expression::Interpolation(parts) => folder.fold_interpolation(parts),
+        Expression::Call(callee, paren, arguments) => folder.fold_call(*callee, paren, arguments),
+        Expression::Assign(name, value, slot) => folder.fold_assign(name, *value, slot),
+        Expression::Lambda(declaration) => folder.fold_lambda(Rc::unwrap_or_clone(declaration)),
+        Expression::CompoundAssign(name, op, value, slot) => folder.fold_compound_assign(name, op, *value, slot),
+        Expression::Update(name, op, prefix, slot) => folder.fold_update(name, op, prefix, slot),
+        Expression::Get(object, name) => folder.fold_get(*object, name),
+        Expression::Index(object, bracket, index) => folder.fold_index(*object, bracket, *index),
+        Expression::Set(object, name, value) => folder.fold_set(*object, name, *value),
+        Expression::This(keyword, slot) => folder.fold_this(keyword, slot),
+        Expression::Super(keyword, method, slot) => folder.fold_super(keyword, method, slot),
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn fold_function<F: Fold + ?Sized>(folder: &mut F, declaration: FunctionDeclaration) -> FunctionDeclaration {
+    let FunctionDeclaration { name, params, body } = declaration;
+    let body = body.into_iter().map(|statement| folder.fold_statement(statement)).collect();
+    FunctionDeclaration { name, params, body }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::{Literal, TokenType};
+    use crate::{ast, scanner::scan};
+
+    fn parse(source: &str) -> Vec<Statement> {
+        ast::parse(scan(source, false).expect("source lexes")).expect("source parses")
+    }
+
+    fn variant(node: &impl std::fmt::Debug) -> String {
+        format!("{node:?}").split('(').next().unwrap_or_default().to_string()
+    }
+
+    /// Every statement and expression variant, with a numbered variable in
+    /// each child position.
+    const SOURCE: &str = "
+        class C < Base {
+          m(p) { return super.m(this.f[v1], -v2, (v3), \"${v4}\", v5 or v6, v7.g, v8 + 1) }
+        }
+        let x = v9
+        x = v10
+        x += v11
+        x++
+        let l = fn(q) => v12
+        l.h = v13
+        print v14
+        if (v15) print v16 else { v17 }
+        while (v18) { v19 }
+        fn f() { return v20 }
+    ";
+
+    #[derive(Default)]
+    struct Census {
+        variables: Vec<String>,
+        statements: Vec<String>,
+        expressions: Vec<String>,
+    }
+
+    impl Visitor for Census {
+        fn visit_statement(&mut self, statement: &Statement) {
+            self.statements.push(variant(statement));
+            walk_statement(self, statement);
+        }
+
+        fn visit_expression(&mut self, expr: &Expression) {
+            self.expressions.push(variant(expr));
+            walk_expression(self, expr);
+        }
+
+        fn visit_variable(&mut self, name: &Token, _slot: Option<Slot>) {
+            self.variables.push(name.lexeme.clone());
+        }
+    }
+
+    #[test]
+    fn visitor_reaches_every_variant() {
+        let mut census = Census::default();
+        for statement in &parse(SOURCE) {
+            census.visit_statement(statement);
+        }
+
+        let mut expected: Vec<String> = (1..=20).map(|n| format!("v{n}")).collect();
+        expected.extend(["Base".to_string(), "l".to_string()]);
+        expected.sort();
+        census.variables.sort();
+        assert_eq!(census.variables, expected);
+
+        for kind in ["Expression", "Print", "Var", "Block", "If", "While", "Function", "Return", "Class"] {
+            assert!(census.statements.iter().any(|seen| seen == kind), "never visited {kind}");
+        }
+        for kind in [
+            "Literal", "Unary", "Binary", "Logical", "Grouping", "Variable", "Interpolation", "Call", "Assign", "Lambda",
+            "CompoundAssign", "Update", "Get", "Index", "Set", "This", "Super",
+        ] {
+            assert!(census.expressions.iter().any(|seen| seen == kind), "never visited {kind}");
+        }
+    }
+
+    /// Replaces arithmetic on two number literals with its result.
+    struct ConstantFolder;
+
+    impl Fold for ConstantFolder {
+        fn fold_binary(&mut self, left: Expression, op: Token, right: Expression) -> Expression {
+            let left = self.fold_expression(left);
+            let right = self.fold_expression(right);
+            if let (Expression::Literal(a), Expression::Literal(b)) = (&left, &right) {
+                if let (Literal::Number(a), Literal::Number(b)) = (&a.literal, &b.literal) {
+                    let value = match op.token_type {
+                        TokenType::PLUS => Some(a + b),
+                        TokenType::MINUS => Some(a - b),
+                        TokenType::STAR => Some(a * b),
+                        TokenType::SLASH => Some(a / b),
+                        _ => None,
+                    };
+                    if let Some(value) = value {
+                        let span = left.span().to(right.span());
+                        return Expression::Literal(Token::new(TokenType::NUMBER, value.to_string(), Literal::Number(value), span));
+                    }
+                }
+            }
+            Expression::Binary(Box::new(left), op, Box::new(right))
+        }
+    }
+
+    #[test]
+    fn fold_reaches_every_variant() {
+        let mut source = SOURCE.to_string();
+        for n in (1..=20).rev() {
+            source = source.replace(&format!("v{n}"), "(1 + 2 * 3)");
+        }
+        let statements = parse(&source);
+        let before = ast::print(&statements, false);
+        assert_eq!(before.matches("(+ 1 (* 2 3))").count(), 20);
+
+        let folded: Vec<Statement> = statements.into_iter().map(|statement| ConstantFolder.fold_statement(statement)).collect();
+        let after = ast::print(&folded, false);
+        assert!(!after.contains("(+ 1") && !after.contains("(* 2"), "{after}");
+        assert_eq!(after.matches("(group 7)").count(), 20, "{after}");
+    }
+}
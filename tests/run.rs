@@ -0,0 +1,399 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `source` as a script and returns its stdout and exit status.
+fn run(name: &str, source: &str) -> (String, i32) {
+    run_with(name, &[], source)
+}
+
+/// Like `run`, passing `flags` before the script path.
+fn run_with(name: &str, flags: &[&str], source: &str) -> (String, i32) {
+    let path = env::temp_dir().join(format!("intrprtr-rs-{name}-{}.lox", std::process::id()));
+    fs::write(&path, source).expect("failed to write the script");
+    let output = Command::new(env!("CARGO_BIN_EXE_intrprtr-rs"))
+        .args(flags)
+        .arg(&path)
+        .output()
+        .expect("failed to run the interpreter");
+    fs::remove_file(&path).ok();
+    (String::from_utf8_lossy(&output.stdout).into_owned(), output.status.code().expect("exited normally"))
+}
+
+/// Feeds `input` to the REPL line by line and returns its stdout and exit status.
+fn repl(input: &str) -> (String, i32) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_intrprtr-rs"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start the REPL");
+    child.stdin.take().expect("piped stdin").write_all(input.as_bytes()).expect("failed to write to the REPL");
+    let output = child.wait_with_output().expect("failed to run the REPL");
+    (String::from_utf8_lossy(&output.stdout).into_owned(), output.status.code().expect("exited normally"))
+}
+
+#[test]
+fn a_clean_script_exits_zero() {
+    assert_eq!(run("clean", "print 1 + 2\n"), ("3\n".to_string(), 0));
+}
+
+#[test]
+fn lexer_errors_refuse_the_program() {
+    for (name, source) in [("exponent", "print 1e\n"), ("escape", "print \"\\q\"\n"), ("decimal", "print 1.\n")] {
+        let (stdout, status) = run(name, &(source.to_string() + "print \"ran\"\n"));
+        assert_eq!(status, 65, "{source:?}");
+        assert!(stdout.contains("error"), "{source:?}: {stdout}");
+        assert!(!stdout.contains("ran"), "{source:?}: {stdout}");
+    }
+}
+
+#[test]
+fn parse_and_resolve_errors_exit_65() {
+    assert_eq!(run("parse", "print 1 +\nprint \"ran\"\n").1, 65);
+    assert_eq!(run("resolve", "return 1\n").1, 65);
+}
+
+#[test]
+fn exit_sets_the_status() {
+    assert_eq!(run("exit-3", "print 1\nexit(3)\nprint 2\n"), ("1\n".to_string(), 3));
+    assert_eq!(run("exit-255", "exit(255)\n").1, 255);
+}
+
+#[test]
+fn exit_rejects_codes_that_do_not_fit_a_status() {
+    for (name, code) in [("exit-256", "256"), ("exit-negative", "-1"), ("exit-fraction", "1.5"), ("exit-string", "\"1\"")] {
+        let (stdout, status) = run(name, &("exit(".to_string() + code + ")\nprint \"after\"\n"));
+        assert!(stdout.contains("exit code must be an integer from 0 to 255"), "{code}: {stdout}");
+        assert!(!stdout.contains("after"), "{code}: {stdout}");
+        assert_eq!(status, 70, "{code}");
+    }
+}
+
+#[test]
+fn bang_negates_truthiness_of_any_value() {
+    let source = "let x = null\nif (!x) print \"empty\"\nprint !1\nprint !\"\"\nprint !!0\nprint !false\nprint !(fn() {})\n";
+    assert_eq!(run("bang", source), ("empty\nfalse\nfalse\ntrue\ntrue\nfalse\n".to_string(), 0));
+}
+
+#[test]
+fn calls_with_the_wrong_argument_count_stop_the_program() {
+    let (stdout, status) = run("arity", "fn f(a) {}\nf(1, 2)\nprint \"after\"\n");
+    assert!(stdout.contains("expected 1 arguments but got 2"), "{stdout}");
+    assert!(!stdout.contains("after"), "{stdout}");
+    assert_eq!(status, 70);
+
+    let (stdout, status) = run("not-callable", "let x = 1\nx()\nprint \"after\"\n");
+    assert!(stdout.contains("can only call functions"), "{stdout}");
+    assert!(!stdout.contains("after"), "{stdout}");
+    assert_eq!(status, 70);
+}
+
+#[test]
+fn the_repl_keeps_going_after_a_runtime_error() {
+    let (stdout, status) = repl("fn f(a) {}\nf()\nprint \"after\"\n");
+    assert!(stdout.contains("expected 1 arguments but got 0"), "{stdout}");
+    assert!(stdout.contains("after"), "{stdout}");
+    assert_eq!(status, 0);
+}
+
+#[test]
+fn a_failed_class_declaration_does_not_shift_later_locals() {
+    let (stdout, status) = run("bad-superclass", "{\nlet A = 1\nclass B < A {}\nlet c = 2\nlet d = 3\nprint c\nprint d\n}\n");
+    assert!(stdout.contains("superclass must be a class"), "{stdout}");
+    assert!(!stdout.lines().any(|line| line == "2" || line == "3"), "{stdout}");
+    assert_eq!(status, 70);
+
+    let source = "{\nclass A { get() { return 1 } }\nclass B < A {}\nlet c = 2\nlet d = 3\nprint B().get() + c + d\n}\n";
+    assert_eq!(run("local-classes", source), ("6\n".to_string(), 0));
+}
+
+#[test]
+fn unbounded_recursion_is_a_runtime_error() {
+    let (stdout, status) = run("recursion", "fn f() { return f() }\nf()\nprint \"after\"\n");
+    assert!(stdout.contains("stack overflow"), "{stdout}");
+    assert!(!stdout.contains("after"), "{stdout}");
+    assert_eq!(status, 70);
+
+    let source = "fn depth(n) { if (n == 0) return 0\nreturn depth(n - 1) + 1 }\nprint depth(999)\n";
+    assert_eq!(run("deep-recursion", source), ("999\n".to_string(), 0));
+}
+
+#[test]
+fn operator_type_errors_stop_the_program() {
+    for (name, source, message) in [
+        ("add-mixed", "print 1 + \"a\"", "invalid operands"),
+        ("subtract-strings", "print \"a\" - \"b\"", "invalid operator"),
+        ("compare-mixed", "print 1 < true", "invalid operands"),
+        ("negate-string", "print -\"a\"", "invalid operands"),
+    ] {
+        let (stdout, status) = run(name, &(source.to_string() + "\nprint \"after\"\n"));
+        assert!(stdout.contains(message), "{source}: {stdout}");
+        assert!(!stdout.contains("null") && !stdout.contains("after"), "{source}: {stdout}");
+        assert_eq!(status, 70, "{source}");
+    }
+}
+
+#[test]
+fn property_errors_stop_the_program() {
+    for (name, source, message) in [
+        ("undefined-property", "class A {}\nprint A().x", "undefined property 'x'"),
+        ("number-property", "let o = 1\nprint o.x", "only instances have properties"),
+        ("number-field", "let o = 1\no.x = 2", "only instances have fields"),
+        ("undefined-super", "class A {}\nclass B < A { f() { return super.g() } }\nB().f()", "undefined property 'g'"),
+    ] {
+        let (stdout, status) = run(name, &(source.to_string() + "\nprint \"after\"\n"));
+        assert!(stdout.contains(message), "{source}: {stdout}");
+        assert!(!stdout.contains("after"), "{source}: {stdout}");
+        assert_eq!(status, 70, "{source}");
+    }
+}
+
+#[test]
+fn initializers_take_the_class_arguments() {
+    let source = "class P {\ninit(x, y) { this.x = x\nthis.y = y }\n}\nlet p = P(1, 2)\nprint p.x + p.y\n";
+    assert_eq!(run("init-args", source), ("3\n".to_string(), 0));
+
+    let (stdout, status) = run("init-arity", "class P { init(x) {} }\nP()\nprint \"after\"\n");
+    assert!(stdout.contains("expected 1 arguments but got 0"), "{stdout}");
+    assert_eq!(status, 70);
+
+    let (stdout, status) = run("no-init-arity", "class P {}\nP(1)\n");
+    assert!(stdout.contains("expected 0 arguments but got 1"), "{stdout}");
+    assert_eq!(status, 70);
+}
+
+#[test]
+fn fields_shadow_methods() {
+    let source = "class A { m() { return \"method\" } }\nlet a = A()\nprint a.m()\na.m = fn() { return \"field\" }\nprint a.m()\nprint A().m()\n";
+    assert_eq!(run("fields", source), ("method\nfield\nmethod\n".to_string(), 0));
+}
+
+#[test]
+fn bound_methods_keep_their_instance() {
+    let source = "class A {\ninit(n) { this.n = n }\nget() { return this.n }\n}\nlet get = A(5).get\nlet other = A(6)\nother.f = get\nprint get()\nprint other.f()\n";
+    assert_eq!(run("bound", source), ("5\n5\n".to_string(), 0));
+}
+
+#[test]
+fn super_calls_the_superclass_method() {
+    let source = "class A {\ninit(n) { this.n = n }\nname() { return \"A${this.n}\" }\n}\n\
+        class B < A {\ninit(n) { super.init(n + 1) }\nname() { return \"B\" + super.name() }\n}\n\
+        class C < B {}\nprint C(1).name()\n";
+    assert_eq!(run("super", source), ("BA2\n".to_string(), 0));
+}
+
+#[test]
+fn init_returns_the_instance() {
+    let source = "class A {\ninit() { this.n = 1\nreturn }\n}\nlet a = A()\na.n = 2\nprint a.init() == a\nprint a.n\n";
+    assert_eq!(run("init-this", source), ("true\n1\n".to_string(), 0));
+}
+
+#[test]
+fn compound_assignment_applies_each_operator() {
+    let source = "let x = 10\nx += 5\nprint x\nx -= 3\nprint x\nx *= 2\nprint x\nx /= 8\nprint x\nx %= 2\nprint x\nlet s = \"a\"\ns += \"b\"\nprint s\nprint (x += 1)\n";
+    assert_eq!(run("compound", source), ("15\n12\n24\n3\n1\nab\n2\n".to_string(), 0));
+}
+
+#[test]
+fn compound_assignment_reads_the_variable_once() {
+    let source = "let x = 1\nfn bump() { x = 10\nreturn 1 }\nx += bump()\nprint x\n";
+    assert_eq!(run("compound-once", source), ("2\n".to_string(), 0));
+}
+
+#[test]
+fn prefix_and_postfix_updates_return_the_new_and_old_values() {
+    let source = "let i = 1\nprint i++\nprint i\nprint ++i\nprint i--\nprint --i\nprint i\n{\nlet j = 5\nj++\nprint j\n}\n";
+    assert_eq!(run("update", source), ("1\n2\n3\n3\n1\n1\n6\n".to_string(), 0));
+}
+
+#[test]
+fn assignment_type_errors_stop_without_storing() {
+    let (stdout, status) = run("compound-error", "let x = 1\nfn f() { x += \"a\" }\nf()\nprint x\n");
+    assert!(stdout.contains("invalid operands"), "{stdout}");
+    assert_eq!(status, 70);
+    let (stdout, _) = repl("let x = 1\nx += \"a\"\nprint x\n");
+    assert!(stdout.contains("invalid operands"), "{stdout}");
+    assert!(stdout.contains("\x1b[0m1\n"), "{stdout}");
+
+    let (stdout, status) = run("update-error", "let s = \"a\"\ns++\nprint \"after\"\n");
+    assert!(stdout.contains("can only increment or decrement numbers"), "{stdout}");
+    assert!(!stdout.contains("after"), "{stdout}");
+    assert_eq!(status, 70);
+}
+
+#[test]
+fn bad_string_indexes_stop_the_program() {
+    for (name, source, message) in [
+        ("index-range", "print \"abc\"[5]", "index 5 out of range for string of length 3"),
+        ("index-negative", "print \"abc\"[-1]", "string index must be a non-negative integer"),
+        ("index-fraction", "print \"abc\"[0.5]", "string index must be a non-negative integer"),
+        ("index-string", "print \"abc\"[\"0\"]", "string index must be a number"),
+        ("index-number", "print 12[0]", "can only index strings"),
+    ] {
+        let (stdout, status) = run(name, &(source.to_string() + "\nprint \"after\"\n"));
+        assert!(stdout.contains(message), "{source}: {stdout}");
+        assert!(!stdout.contains("null") && !stdout.contains("after"), "{source}: {stdout}");
+        assert_eq!(status, 70, "{source}");
+    }
+    assert_eq!(run("index", "print \"héllo\"[1]\n"), ("é\n".to_string(), 0));
+}
+
+#[test]
+fn assignment_chains_to_the_right() {
+    let source = "let a = 1\nlet b = 2\na = b = 3\nprint a\nprint b\n{\nlet c = 0\nlet d = 0\nc = d = a + 1\nprint c + d\n}\n";
+    assert_eq!(run("assign-chain", source), ("3\n3\n8\n".to_string(), 0));
+}
+
+#[test]
+fn assigning_an_undefined_name_is_an_error() {
+    let (stdout, status) = run("assign-undefined", "x = 1\nprint \"after\"\n");
+    assert!(stdout.contains("undefined variable 'x'"), "{stdout}");
+    assert_eq!(status, 65);
+
+    // The REPL lets functions name globals defined later, so the check happens when they run.
+    let (stdout, status) = repl("fn f() { w = 2 }\nf()\nprint \"after\"\nlet w = 1\nf()\nprint w\n");
+    assert!(stdout.contains("undefined variable 'w'"), "{stdout}");
+    assert!(stdout.contains("after"), "{stdout}");
+    assert!(stdout.contains("\x1b[0m2\n"), "{stdout}");
+    assert_eq!(status, 0);
+}
+
+#[test]
+fn blocks_shadow_outer_names() {
+    let source = "let a = \"global\"\n{\nlet a = \"outer\"\n{\nlet a = \"inner\"\nprint a\n}\nprint a\n}\nprint a\n";
+    assert_eq!(run("shadow", source), ("inner\nouter\nglobal\n".to_string(), 0));
+
+    let source = "let a = 1\n{\nlet b = 2\n{\na = a + b\nb = 5\n}\nprint b\n}\nprint a\n";
+    assert_eq!(run("assign-outward", source), ("5\n3\n".to_string(), 0));
+}
+
+#[test]
+fn block_locals_are_discarded_on_exit() {
+    let (stdout, status) = run("discarded", "{\nlet inner = 1\nprint inner\n}\nprint inner\n");
+    assert!(stdout.contains("undefined variable 'inner' on line [5:7]"), "{stdout}");
+    assert_eq!(status, 65);
+
+    let source = "let a = 1\n{\nlet a = 2\nprint a\n}\nprint a\n";
+    assert_eq!(run("restored", source), ("2\n1\n".to_string(), 0));
+}
+
+#[test]
+fn logical_operators_skip_the_right_operand() {
+    let source = "let calls = 0\nfn hit() { calls += 1\nreturn true }\nprint false and hit()\nprint true or hit()\nprint calls\nprint true and hit()\nprint false or hit()\nprint calls\n";
+    assert_eq!(run("short-circuit", source), ("false\ntrue\n0\ntrue\ntrue\n2\n".to_string(), 0));
+}
+
+#[test]
+fn logical_operators_return_the_deciding_operand() {
+    let source = "print null or \"default\"\nprint \"set\" or \"default\"\nprint 0 and \"then\"\nprint null and \"then\"\nprint false or null\nprint 1 and 2 or 3\n";
+    assert_eq!(run("logical-values", source), ("default\nset\nthen\nnull\nnull\n2\n".to_string(), 0));
+}
+
+#[test]
+fn lambdas_capture_their_closure() {
+    let source = "fn adder(n) {\nreturn fn(x) => x + n\n}\nlet add2 = adder(2)\nlet add5 = adder(5)\nprint add2(1)\nprint add5(1)\n";
+    assert_eq!(run("arrow-closure", source), ("3\n6\n".to_string(), 0));
+
+    let source = "fn counter() {\nlet count = 0\nreturn fn() {\ncount += 1\nreturn count\n}\n}\nlet next = counter()\nnext()\nprint next()\nprint counter()()\n";
+    assert_eq!(run("block-closure", source), ("2\n1\n".to_string(), 0));
+}
+
+#[test]
+fn lambdas_are_values() {
+    let source = "let twice = fn(f, x) => f(f(x))\nprint twice(fn(x) => x * 3, 2)\nprint fn() {}\nprint (fn(a, b) { return a - b })(5, 2)\n";
+    assert_eq!(run("lambda-values", source), ("18\n<fn>\n3\n".to_string(), 0));
+}
+
+#[test]
+fn dump_tokens_prints_positions_and_does_not_run() {
+    let expected = "1:1 PRINT \"print\"\n1:7 NUMBER \"1.5\" Number(1.5)\n1:11 PLUS \"+\"\n1:13 STRING \"\\\"a\\\"\" String(\"a\")\n1:16 SEMICOLON \"\\n\"\n2:1 EOF \"\"\n";
+    assert_eq!(run_with("dump-tokens", &["--dump-tokens"], "print 1.5 + \"a\"\n"), (expected.to_string(), 0));
+
+    let (stdout, status) = run_with("dump-tokens-error", &["--dump-tokens"], "print 1e\n");
+    assert!(stdout.contains("expected digits in the exponent"), "{stdout}");
+    assert_eq!(status, 65);
+}
+
+#[test]
+fn dump_ast_prints_the_tree_and_does_not_run() {
+    let source = "let x = 1 + 2 * 3\nif (x > 1) print x else print -x\n";
+    let expected = "(let x (+ 1 (* 2 3)))\n(if (> x 1)\n  (print x)\n  (print (- x)))\n";
+    assert_eq!(run_with("dump-ast", &["--dump-ast"], source), (expected.to_string(), 0));
+
+    let (stdout, status) = run_with("dump-ast-error", &["--dump-ast"], "print (1\n");
+    assert!(stdout.contains("expecting ')'"), "{stdout}");
+    assert_eq!(status, 65);
+}
+
+#[test]
+fn resolver_errors_refuse_the_program() {
+    for (name, source, message) in [
+        ("use-before-definition", "print a\nlet a = 1", "'a' is used before its definition on line [1:7]"),
+        ("undefined", "print nothing", "undefined variable 'nothing' on line [1:7]"),
+        ("own-initializer", "{\nlet a = 1\n{\nlet a = a\nprint a\n}\n}", "can't read local variable 'a' in its own initializer on line [4:9]"),
+        ("duplicate", "{\nlet a = 1\nlet a = 2\nprint a\n}", "'a' is already declared in this scope on line [3:5]"),
+        ("top-level-return", "print 1\nreturn 1", "can't return from top-level code on line [2:1]"),
+    ] {
+        let (stdout, status) = run(name, &(source.to_string() + "\nprint \"ran\"\n"));
+        assert!(stdout.contains(message), "{source}: {stdout}");
+        assert!(!stdout.contains("ran"), "{source}: {stdout}");
+        assert_eq!(status, 65, "{source}");
+    }
+}
+
+#[test]
+fn unused_locals_warn_but_still_run() {
+    let source = "fn f(unused_param) {\nlet b = 1\nlet _ignored = 2\nlet a = 3\nreturn 0\n}\nprint f(0)\n";
+    let warnings = "\x1b[1;33mwarning: \x1b[0munused local variable 'b' on line [2:5] to [2:6]\n\x1b[1;33mwarning: \x1b[0munused local variable 'a' on line [4:5] to [4:6]\n";
+    assert_eq!(run("unused", source), (warnings.to_string() + "0\n", 0));
+}
+
+#[test]
+fn warnings_are_dropped_when_the_program_is_refused() {
+    let (stdout, status) = run("unused-refused", "{\nlet unused = 1\n}\nreturn 1\n");
+    assert!(stdout.contains("can't return from top-level code"), "{stdout}");
+    assert!(!stdout.contains("warning"), "{stdout}");
+    assert_eq!(status, 65);
+}
+
+#[test]
+fn interpolation_nests_and_joins_adjacent_segments() {
+    let source = "let n = 3\nprint \"${\"in${n}\"}\"\nprint \"${1}${2}\"\nprint \"[${n}${n + 1}]\"\nprint \"\\${n} is ${n}\"\n";
+    assert_eq!(run("interpolation", source), ("in3\n12\n[34]\n${n} is 3\n".to_string(), 0));
+}
+
+#[test]
+fn interpolation_stringifies_like_print() {
+    let source = "class A {}\nfn f() {}\nlet values = \"${1.5} ${2} ${true} ${null} ${f} ${A} ${A()} ${exit}\"\nprint values\nprint 1.5\nprint 2\n";
+    let expected = "1.5 2 true null <fn f> A <A instance> <native fn exit>\n1.5\n2\n";
+    assert_eq!(run("interpolation-values", source), (expected.to_string(), 0));
+}
+
+#[test]
+fn an_unclosed_interpolation_is_reported_where_it_opens() {
+    let (stdout, status) = run("interpolation-open", "print \"${1\"\nprint \"ran\"\n");
+    assert!(stdout.contains("unterminated interpolation: expected '}' before the closing quote on line [1:8]"), "{stdout}");
+    assert!(!stdout.contains("unterminated string"), "{stdout}");
+    assert_eq!(status, 65);
+}
+
+#[test]
+fn closures_share_and_mutate_their_environment() {
+    let source = "fn make() {\nlet count = 0\nfn inc() { count = count + 1\nreturn count }\nfn get() { return count }\nreturn fn(op) { if (op == \"inc\") return inc()\nreturn get() }\n}\n\
+        let a = make()\nlet b = make()\na(\"inc\")\na(\"inc\")\nb(\"inc\")\nprint a(\"get\")\nprint b(\"get\")\n";
+    assert_eq!(run("counter", source), ("2\n1\n".to_string(), 0));
+}
+
+#[test]
+fn return_unwinds_through_blocks_and_loops() {
+    let source = "fn find(limit) {\nlet i = 0\nwhile (true) {\n{\nlet j = i * i\nif (j > limit) {\nreturn i\n}\n}\ni += 1\n}\nprint \"unreachable\"\n}\n\
+        print find(10)\nfn nothing() {\nwhile (true) { return }\n}\nprint nothing()\nlet after = 1\nprint after\n";
+    assert_eq!(run("return-unwind", source), ("4\nnull\n1\n".to_string(), 0));
+}
+
+#[test]
+fn diagnostics_give_the_whole_source_range() {
+    let (stdout, _) = run("range", "let x = 1\nprint x + (\n  \"a\")\n");
+    assert!(stdout.contains("invalid operands on line [2:7] to [3:6]"), "{stdout}");
+}